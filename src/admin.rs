@@ -0,0 +1,155 @@
+//! Administrative endpoints under `/_admin`, guarded by a bearer token.
+//!
+//! - `POST /_admin/regenerate/<path>` streams a fresh take of a page, keeping the old one.
+//! - `POST /_admin/rollback/<path>?version=N` makes an earlier version live again, unless the
+//!   page is being generated.
+//! - `GET /_admin/history/<path>` lists the recorded versions.
+use axum::Router;
+use axum::body::Body;
+use axum::extract::{Path, Query, Request, State};
+use axum::http::header::AUTHORIZATION;
+use axum::http::{Response, StatusCode};
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Json};
+use axum::routing::{get, post};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use time::format_description::well_known::Rfc3339;
+
+use crate::generation::Claim;
+use crate::{AppState, generate_path};
+
+#[derive(Deserialize)]
+struct VersionQuery {
+    version: u32,
+}
+
+#[derive(Serialize)]
+struct History {
+    path: String,
+    current: Option<u32>,
+    versions: Vec<VersionEntry>,
+}
+
+#[derive(Serialize)]
+struct VersionEntry {
+    version: u32,
+    size: u64,
+    created: String,
+}
+
+pub fn router(token: String) -> Router<AppState> {
+    Router::new()
+        .route("/regenerate/{*path}", post(regenerate))
+        .route("/rollback/{*path}", post(rollback))
+        .route("/history/{*path}", get(history))
+        .layer(middleware::from_fn_with_state(
+            Arc::<str>::from(token),
+            authorize,
+        ))
+}
+
+async fn authorize(
+    State(token): State<Arc<str>>,
+    req: Request,
+    next: Next,
+) -> Result<Response<Body>, StatusCode> {
+    let authorized = req
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .is_some_and(|given| constant_time_eq(given.as_bytes(), token.as_bytes()));
+
+    if authorized {
+        Ok(next.run(req).await)
+    } else {
+        Err(StatusCode::UNAUTHORIZED)
+    }
+}
+
+/// Compares without stopping at the first difference, so response times don't tell how much of
+/// a guess was right.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len()
+        && std::hint::black_box(a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y))) == 0
+}
+
+async fn regenerate(
+    Path(path): Path<String>,
+    State(state): State<AppState>,
+) -> Result<Response<Body>, StatusCode> {
+    generate_path(state, &path, None, None, None, true).await
+}
+
+async fn rollback(
+    Path(path): Path<String>,
+    Query(VersionQuery { version }): Query<VersionQuery>,
+    State(state): State<AppState>,
+) -> Result<StatusCode, StatusCode> {
+    let (url, _) = state.store.resolve(&path)?;
+
+    // A generation finishing afterwards would replace the version rolled back to
+    let in_flight = match state.generations.claim(&url) {
+        Claim::Owner(in_flight) => in_flight,
+        Claim::Joiner(_) => return Err(StatusCode::CONFLICT),
+    };
+
+    let result = state.store.rollback(&url, version).await;
+
+    // Requests that came in meanwhile find the file now
+    in_flight
+        .hub()
+        .finish(Err("the page was rolled back, reload it".to_string()));
+
+    match result {
+        Ok(()) => {
+            state.search.refresh(&state.store, &url).await;
+            Ok(StatusCode::NO_CONTENT)
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Err(StatusCode::NOT_FOUND),
+        Err(e) => {
+            eprintln!("{e}");
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+async fn history(
+    Path(path): Path<String>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let (url, _) = state.store.resolve(&path)?;
+
+    let versions = state.store.versions(&url).await.map_err(|e| {
+        eprintln!("{e}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    Ok(Json(History {
+        path: url.to_string_lossy().into_owned(),
+        current: state.store.current_version(&url).await,
+        versions: versions
+            .into_iter()
+            .map(|version| VersionEntry {
+                version: version.number,
+                size: version.size,
+                created: version.created.format(&Rfc3339).unwrap_or_default(),
+            })
+            .collect(),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tokens_are_compared_whole() {
+        assert!(constant_time_eq(b"secret", b"secret"));
+        assert!(!constant_time_eq(b"secret", b"secreT"));
+        assert!(!constant_time_eq(b"secret", b"secret2"));
+        assert!(!constant_time_eq(b"", b"secret"));
+        assert!(constant_time_eq(b"", b""));
+    }
+}
@@ -0,0 +1,438 @@
+//! JSON API over the page index, for tooling that would otherwise scrape `/`.
+//!
+//! - `GET /_api/pages` lists every page.
+//! - `GET /_api/search?q=` ranks pages against a query and includes match snippets.
+//!
+//! Both accept `domain`, `ext`, `sort` (`relevance`, `newest`, `oldest`, `path` or `size`),
+//! `limit` and the `cursor` returned with the previous page of results. Scores change as pages are
+//! generated, so a relevance cursor continues after the last item wherever it ranks now.
+use axum::Router;
+use axum::extract::{Query, State};
+use axum::http::StatusCode;
+use axum::response::Json;
+use axum::routing::get;
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::time::{Duration, SystemTime};
+use time::OffsetDateTime;
+use time::format_description::well_known::Rfc3339;
+
+use crate::AppState;
+use crate::search::{self, Document, Hit, Snippet};
+use crate::vhost::{self, VirtualHosts};
+
+const DEFAULT_LIMIT: usize = 50;
+const MAX_LIMIT: usize = 200;
+
+#[derive(Deserialize)]
+struct Params {
+    q: Option<String>,
+    domain: Option<String>,
+    ext: Option<String>,
+    sort: Option<Sort>,
+    limit: Option<usize>,
+    cursor: Option<String>,
+}
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+enum Sort {
+    Relevance,
+    Newest,
+    Oldest,
+    Path,
+    Size,
+}
+
+#[derive(Serialize)]
+struct Page {
+    items: Vec<Item>,
+    /// Pass as `cursor` to get the next page, absent on the last one.
+    next_cursor: Option<String>,
+}
+
+#[derive(Serialize)]
+struct Item {
+    path: String,
+    domain: String,
+    /// Where the page is served, e.g. `/example.com/about.html`, or
+    /// `//example.com.web2050.local/about.html` with virtual hosts.
+    url: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    title: Option<String>,
+    size: u64,
+    mime: String,
+    /// RFC 3339
+    created: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    score: Option<f64>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    snippets: Vec<Snippet>,
+}
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/pages", get(pages))
+        .route("/search", get(search))
+}
+
+async fn pages(
+    Query(params): Query<Params>,
+    State(state): State<AppState>,
+) -> Result<Json<Page>, StatusCode> {
+    let query = search::Query::default()
+        .with_domain(params.domain.clone())
+        .with_extension(params.ext.clone());
+
+    let hits = state.search.search(&query);
+    paginate(hits, &params, state.vhosts.as_deref(), Sort::Newest, false)
+}
+
+async fn search(
+    Query(params): Query<Params>,
+    State(state): State<AppState>,
+) -> Result<Json<Page>, StatusCode> {
+    let query = search::Query::parse(params.q.as_deref().unwrap_or_default())
+        .with_domain(params.domain.clone())
+        .with_extension(params.ext.clone());
+
+    if query.is_empty() {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    let hits = state.search.search(&query);
+    paginate(
+        hits,
+        &params,
+        state.vhosts.as_deref(),
+        Sort::Relevance,
+        true,
+    )
+}
+
+fn paginate(
+    mut hits: Vec<Hit>,
+    params: &Params,
+    vhosts: Option<&VirtualHosts>,
+    default: Sort,
+    scored: bool,
+) -> Result<Json<Page>, StatusCode> {
+    let sort = params.sort.unwrap_or(default);
+    let limit = params.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+
+    hits.sort_by(|a, b| compare(sort, a, b));
+
+    // Keyset pagination, so pages generated in the meantime don't shift the results
+    if let Some(cursor) = &params.cursor {
+        let after = decode_cursor(cursor).ok_or(StatusCode::BAD_REQUEST)?;
+
+        // Scores change with every generated page, as the rarity of terms does. Continuing
+        // after where the last item ranks now keeps the results in order as long as it is
+        // still there, though whatever moved across it is skipped or repeated.
+        let position = match sort {
+            Sort::Relevance => hits
+                .iter()
+                .position(|hit| hit.document.path == after.document.path),
+            _ => None,
+        };
+
+        match position {
+            Some(position) => drop(hits.drain(..=position)),
+            None => hits.retain(|hit| compare(sort, hit, &after) == Ordering::Greater),
+        }
+    }
+
+    let next_cursor = (hits.len() > limit).then(|| encode_cursor(&hits[limit - 1]));
+    hits.truncate(limit);
+
+    Ok(Json(Page {
+        items: hits
+            .into_iter()
+            .map(|hit| Item {
+                mime: mime_guess::from_path(&hit.document.path)
+                    .first_or_octet_stream()
+                    .to_string(),
+                created: OffsetDateTime::from(hit.document.created)
+                    .format(&Rfc3339)
+                    .unwrap_or_default(),
+                url: vhost::link(vhosts, &hit.document.path),
+                path: hit.document.path,
+                domain: hit.document.domain,
+                title: hit.document.title,
+                size: hit.document.size,
+                score: scored.then_some(hit.score),
+                snippets: hit.snippets,
+            })
+            .collect(),
+        next_cursor,
+    }))
+}
+
+/// Total order for `sort`, ties broken by path.
+fn compare(sort: Sort, a: &Hit, b: &Hit) -> Ordering {
+    let (a_doc, b_doc) = (&a.document, &b.document);
+
+    match sort {
+        Sort::Relevance => b.score.total_cmp(&a.score),
+        Sort::Newest => b_doc.created.cmp(&a_doc.created),
+        Sort::Oldest => a_doc.created.cmp(&b_doc.created),
+        Sort::Path => Ordering::Equal,
+        Sort::Size => b_doc.size.cmp(&a_doc.size),
+    }
+    .then_with(|| a_doc.path.cmp(&b_doc.path))
+}
+
+/// Every sort key of the last item, so a cursor works whatever the sort.
+fn encode_cursor(hit: &Hit) -> String {
+    let created = hit
+        .document
+        .created
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+
+    format!(
+        "{:x}.{created}.{}.{}",
+        hit.score.to_bits(),
+        hit.document.size,
+        hit.document.path
+    )
+}
+
+fn decode_cursor(cursor: &str) -> Option<Hit> {
+    let mut parts = cursor.splitn(4, '.');
+
+    let score = f64::from_bits(u64::from_str_radix(parts.next()?, 16).ok()?);
+    let created: u128 = parts.next()?.parse().ok()?;
+    let size = parts.next()?.parse().ok()?;
+    let path = parts.next()?.to_string();
+
+    let created = SystemTime::UNIX_EPOCH
+        + Duration::new(
+            (created / 1_000_000_000) as u64,
+            (created % 1_000_000_000) as u32,
+        );
+
+    Some(Hit {
+        document: Document {
+            path,
+            domain: String::new(),
+            extension: String::new(),
+            size,
+            created,
+            title: None,
+            summary: String::new(),
+        },
+        score,
+        snippets: Vec::new(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hit(path: &str, score: f64, created: u64, size: u64) -> Hit {
+        Hit {
+            document: Document {
+                path: path.to_string(),
+                domain: path.split('/').next().unwrap().to_string(),
+                extension: String::new(),
+                size,
+                created: SystemTime::UNIX_EPOCH + Duration::from_nanos(created),
+                title: None,
+                summary: String::new(),
+            },
+            score,
+            snippets: Vec::new(),
+        }
+    }
+
+    fn params(sort: Sort, limit: usize, cursor: Option<String>) -> Params {
+        Params {
+            q: None,
+            domain: None,
+            ext: None,
+            sort: Some(sort),
+            limit: Some(limit),
+            cursor,
+        }
+    }
+
+    /// Every page of `hits`, fetched `limit` at a time.
+    fn all_pages(hits: &[Hit], sort: Sort, limit: usize) -> Vec<Vec<String>> {
+        let mut pages = Vec::new();
+        let mut cursor = None;
+
+        loop {
+            let Json(page) = paginate(
+                hits.to_vec(),
+                &params(sort, limit, cursor),
+                None,
+                sort,
+                true,
+            )
+            .unwrap();
+            pages.push(page.items.into_iter().map(|item| item.path).collect());
+
+            match page.next_cursor {
+                Some(next) => cursor = Some(next),
+                None => return pages,
+            }
+        }
+    }
+
+    fn sample() -> Vec<Hit> {
+        vec![
+            hit("a.com/c.html", 1.5, 3, 10),
+            hit("a.com/a.html", 2.0, 1, 30),
+            hit("b.com/v1.2/x.html", 1.5, 3, 20),
+            hit("a.com/b.html", 1.5, 2, 20),
+            hit("b.com/index.html", 0.5, 5, 10),
+        ]
+    }
+
+    #[test]
+    fn cursors_round_trip() {
+        let original = hit("b.com/v1.2/x.tar.gz", -0.25, 1_700_000_000_123_456_789, 42);
+        let decoded = decode_cursor(&encode_cursor(&original)).unwrap();
+
+        assert_eq!(decoded.document.path, original.document.path);
+        assert_eq!(decoded.document.created, original.document.created);
+        assert_eq!(decoded.document.size, 42);
+        assert_eq!(decoded.score.to_bits(), original.score.to_bits());
+    }
+
+    #[test]
+    fn invalid_cursors_are_rejected() {
+        for cursor in [
+            "",
+            "zz.1.2.a.com/x",
+            "1.x.2.a.com/x",
+            "1.2",
+            "1.2.-3.a.com/x",
+        ] {
+            let result = paginate(
+                sample(),
+                &params(Sort::Newest, 2, Some(cursor.to_string())),
+                None,
+                Sort::Newest,
+                false,
+            );
+            assert_eq!(result.err(), Some(StatusCode::BAD_REQUEST), "{cursor}");
+        }
+    }
+
+    #[test]
+    fn pages_cover_every_hit_once() {
+        for sort in [
+            Sort::Relevance,
+            Sort::Newest,
+            Sort::Oldest,
+            Sort::Path,
+            Sort::Size,
+        ] {
+            let mut sorted = sample();
+            sorted.sort_by(|a, b| compare(sort, a, b));
+            let expected: Vec<String> = sorted.into_iter().map(|hit| hit.document.path).collect();
+
+            for limit in 1..=6 {
+                let pages = all_pages(&sample(), sort, limit);
+                assert!(pages.iter().all(|page| page.len() <= limit));
+                assert_eq!(pages.concat(), expected);
+            }
+        }
+    }
+
+    #[test]
+    fn ties_are_broken_by_path() {
+        let pages = all_pages(&sample(), Sort::Relevance, 2);
+        assert_eq!(
+            pages,
+            [
+                vec!["a.com/a.html", "a.com/b.html"],
+                vec!["a.com/c.html", "b.com/v1.2/x.html"],
+                vec!["b.com/index.html"],
+            ]
+        );
+    }
+
+    #[test]
+    fn pagination_survives_new_pages() {
+        // A page generated after the first request sorts before the cursor
+        let Json(first) = paginate(
+            sample(),
+            &params(Sort::Newest, 2, None),
+            None,
+            Sort::Newest,
+            false,
+        )
+        .unwrap();
+        let mut hits = sample();
+        hits.push(hit("c.com/new.html", 1.0, 9, 1));
+
+        let Json(second) = paginate(
+            hits,
+            &params(Sort::Newest, 2, first.next_cursor),
+            None,
+            Sort::Newest,
+            false,
+        )
+        .unwrap();
+        let second: Vec<String> = second.items.into_iter().map(|item| item.path).collect();
+        assert_eq!(second, ["b.com/v1.2/x.html", "a.com/b.html"]);
+    }
+
+    #[test]
+    fn relevance_survives_rescoring() {
+        let Json(first) = paginate(
+            sample(),
+            &params(Sort::Relevance, 2, None),
+            None,
+            Sort::Relevance,
+            true,
+        )
+        .unwrap();
+
+        // Another page made every term rarer, lowering all scores
+        let hits: Vec<Hit> = sample()
+            .into_iter()
+            .map(|mut hit| {
+                hit.score /= 2.0;
+                hit
+            })
+            .collect();
+
+        let Json(second) = paginate(
+            hits,
+            &params(Sort::Relevance, 2, first.next_cursor),
+            None,
+            Sort::Relevance,
+            true,
+        )
+        .unwrap();
+        let second: Vec<String> = second.items.into_iter().map(|item| item.path).collect();
+        assert_eq!(second, ["a.com/c.html", "b.com/v1.2/x.html"]);
+    }
+
+    #[test]
+    fn items_link_to_their_host() {
+        let url = |vhosts: Option<&VirtualHosts>| {
+            let Json(page) = paginate(
+                vec![hit("example.com/docs/about.html", 1.0, 1, 1)],
+                &params(Sort::Path, 1, None),
+                vhosts,
+                Sort::Path,
+                false,
+            )
+            .unwrap();
+            page.items[0].url.clone()
+        };
+
+        assert_eq!(url(None), "/example.com/docs/about.html");
+        assert_eq!(
+            url(Some(&VirtualHosts::new("web2050.local"))),
+            "//example.com.web2050.local/docs/about.html"
+        );
+    }
+}
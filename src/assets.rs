@@ -1,53 +1,318 @@
-use futures_util::future::try_join_all;
+//! Reads the files of a domain, to show them to the model.
+//!
+//! Only text is read. Binary files, files over the size limits and files that cannot be read are
+//! skipped with a reason instead of failing the generation, and hidden, temporary and backup
+//! files are ignored altogether.
+use futures_util::future::join_all;
 use jwalk::WalkDir;
-use tokio::{fs, io};
+use tokio::fs;
 
 use std::{
     fmt,
     path::{Path, PathBuf},
 };
 
+/// Largest files read, in bytes.
+#[derive(Debug, Clone, Copy)]
+pub struct Limits {
+    pub file: u64,
+    pub total: u64,
+}
+
 pub struct Asset {
-    path: PathBuf,
-    content: String,
+    pub path: PathBuf,
+    pub content: String,
 }
 
-pub struct AssetList(pub Vec<Asset>);
+pub struct Skipped {
+    pub path: PathBuf,
+    pub reason: Reason,
+}
 
-pub async fn read_all_files_in_dir(dir: impl AsRef<Path>) -> io::Result<AssetList> {
-    let mut tasks = Vec::new();
+pub enum Reason {
+    Binary {
+        mime: String,
+        size: u64,
+    },
+    TooLarge {
+        size: u64,
+        limit: u64,
+    },
+    /// Reading it would exceed the total limit.
+    OverTotal {
+        size: u64,
+    },
+    Unreadable(String),
+}
 
-    for entry in WalkDir::new(&dir) {
-        let entry = entry.map_err(io::Error::other)?;
-        let path = entry.path().to_path_buf();
+pub struct AssetList {
+    pub assets: Vec<Asset>,
+    pub skipped: Vec<Skipped>,
+}
 
-        // Skip if not a file
-        if path.is_file() {
-            let task = async move {
-                let content = fs::read_to_string(&path).await?;
-                Ok::<Asset, io::Error>(Asset { path, content })
-            };
-            tasks.push(task);
+pub async fn read_all_files_in_dir(dir: impl AsRef<Path>, limits: Limits) -> AssetList {
+    let mut list = AssetList {
+        assets: Vec::new(),
+        skipped: Vec::new(),
+    };
+
+    // First file in a new domain
+    if !dir.as_ref().is_dir() {
+        return list;
+    }
+
+    // Hidden files include the temporary files of generations in progress
+    let mut files = Vec::new();
+    for entry in WalkDir::new(&dir).skip_hidden(true) {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                let path = e.path().map(Path::to_path_buf).unwrap_or_default();
+                list.skipped.push(Skipped {
+                    path,
+                    reason: Reason::Unreadable(e.to_string()),
+                });
+                continue;
+            }
+        };
+
+        if !entry.file_type().is_file() || is_scratch(&entry.file_name().to_string_lossy()) {
+            continue;
+        }
+
+        let path = entry.path();
+        match entry.metadata() {
+            Ok(metadata) => files.push((path, metadata.len())),
+            Err(e) => list.skipped.push(Skipped {
+                path,
+                reason: Reason::Unreadable(e.to_string()),
+            }),
         }
     }
 
-    let assets = try_join_all(tasks).await?;
-    Ok(AssetList(assets))
+    // Smallest first, so the total limit leaves out as few files as possible
+    files.sort_by(|(a_path, a_size), (b_path, b_size)| (a_size, a_path).cmp(&(b_size, b_path)));
+
+    let mut total = 0;
+    let mut reads = Vec::new();
+
+    for (path, size) in files {
+        if size > limits.file {
+            list.skipped.push(Skipped {
+                path,
+                reason: Reason::TooLarge {
+                    size,
+                    limit: limits.file,
+                },
+            });
+        } else if total + size > limits.total {
+            list.skipped.push(Skipped {
+                path,
+                reason: Reason::OverTotal { size },
+            });
+        } else {
+            total += size;
+            reads.push(read(path, size));
+        }
+    }
+
+    for read in join_all(reads).await {
+        match read {
+            Ok(asset) => list.assets.push(asset),
+            Err(skipped) => list.skipped.push(skipped),
+        }
+    }
+
+    list
 }
-impl fmt::Display for Asset {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        writeln!(f, "{}", self.path.display())?;
-        writeln!(f, "```")?;
-        writeln!(f, "{}", self.content)?;
-        writeln!(f, "```")
+
+async fn read(path: PathBuf, size: u64) -> Result<Asset, Skipped> {
+    let bytes = match fs::read(&path).await {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            return Err(Skipped {
+                path,
+                reason: Reason::Unreadable(e.to_string()),
+            });
+        }
+    };
+
+    // NUL bytes are valid UTF-8, but no text file has them
+    match String::from_utf8(bytes) {
+        Ok(content) if !content.contains('\0') => Ok(Asset { path, content }),
+        _ => Err(Skipped {
+            reason: Reason::Binary {
+                mime: mime_guess::from_path(&path)
+                    .first_or_octet_stream()
+                    .to_string(),
+                size,
+            },
+            path,
+        }),
     }
 }
 
-impl fmt::Display for AssetList {
+/// Editor swap and backup files, and leftovers of interrupted writes.
+fn is_scratch(name: &str) -> bool {
+    name.ends_with('~')
+        || (name.starts_with('#') && name.ends_with('#'))
+        || [".tmp", ".swp", ".swo", ".bak", ".orig", ".partial"]
+            .iter()
+            .any(|extension| name.ends_with(extension))
+}
+
+impl fmt::Display for Reason {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        for asset in &self.0 {
-            writeln!(f, "{asset}\n")?;
+        match self {
+            Self::Binary { mime, size } => write!(f, "binary {mime}, {size} bytes"),
+            Self::TooLarge { size, limit } => {
+                write!(f, "{size} bytes, over the {limit} byte limit")
+            }
+            Self::OverTotal { size } => write!(f, "{size} bytes, over the total limit"),
+            Self::Unreadable(e) => write!(f, "unreadable: {e}"),
         }
-        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A domain directory with `files`, removed again when dropped.
+    struct Domain(PathBuf);
+
+    impl Domain {
+        fn new(name: &str, files: &[(&str, &[u8])]) -> Self {
+            let dir =
+                std::env::temp_dir().join(format!("web2050-assets-{name}-{}", std::process::id()));
+            let _ = std::fs::remove_dir_all(&dir);
+
+            for (path, content) in files {
+                let path = dir.join(path);
+                std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+                std::fs::write(path, content).unwrap();
+            }
+
+            Self(dir)
+        }
+
+        async fn read(&self, file: u64, total: u64) -> (Vec<String>, Vec<(String, String)>) {
+            let list = read_all_files_in_dir(&self.0, Limits { file, total }).await;
+            let relative = |path: &Path| {
+                path.strip_prefix(&self.0)
+                    .unwrap()
+                    .to_string_lossy()
+                    .into_owned()
+            };
+
+            let mut assets: Vec<String> = list
+                .assets
+                .iter()
+                .map(|asset| relative(&asset.path))
+                .collect();
+            let mut skipped: Vec<(String, String)> = list
+                .skipped
+                .iter()
+                .map(|skipped| (relative(&skipped.path), skipped.reason.to_string()))
+                .collect();
+            assets.sort();
+            skipped.sort();
+            (assets, skipped)
+        }
+    }
+
+    impl Drop for Domain {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[tokio::test]
+    async fn text_files_are_read() {
+        let domain = Domain::new(
+            "text",
+            &[
+                ("index.html", b"<p>hi</p>"),
+                ("css/style.css", "p { content: \"ü\" }".as_bytes()),
+                ("favicon.ico", b"\0\0\x01\0"),
+                ("logo.svg", b"\xff\xfe<svg/>"),
+            ],
+        );
+
+        let (assets, skipped) = domain.read(1024, 1024).await;
+        assert_eq!(assets, ["css/style.css", "index.html"]);
+        assert_eq!(
+            skipped,
+            [
+                (
+                    "favicon.ico".to_string(),
+                    "binary image/x-icon, 4 bytes".to_string()
+                ),
+                (
+                    "logo.svg".to_string(),
+                    "binary image/svg+xml, 8 bytes".to_string()
+                ),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn hidden_and_scratch_files_are_ignored() {
+        let domain = Domain::new(
+            "hidden",
+            &[
+                ("index.html", b"x"),
+                (".index.html.123-0.tmp", b"half written"),
+                (".query/search.html/0123456789abcdef.html", b"x"),
+                ("about.html~", b"x"),
+                ("about.html.swp", b"x"),
+                ("#about.html#", b"x"),
+                ("about.html.bak", b"x"),
+                ("about.html.17.partial", b"x"),
+            ],
+        );
+
+        let (assets, skipped) = domain.read(1024, 1024).await;
+        assert_eq!(assets, ["index.html"]);
+        assert!(skipped.is_empty());
+    }
+
+    #[tokio::test]
+    async fn limits_are_respected() {
+        let domain = Domain::new(
+            "limits",
+            &[
+                ("a.txt", &[b'a'; 10]),
+                ("b.txt", &[b'b'; 20]),
+                ("c.txt", &[b'c'; 30]),
+                ("huge.txt", &[b'h'; 100]),
+            ],
+        );
+
+        // Smallest first, so the total leaves out as few files as possible
+        let (assets, skipped) = domain.read(50, 35).await;
+        assert_eq!(assets, ["a.txt", "b.txt"]);
+        assert_eq!(
+            skipped,
+            [
+                (
+                    "c.txt".to_string(),
+                    "30 bytes, over the total limit".to_string()
+                ),
+                (
+                    "huge.txt".to_string(),
+                    "100 bytes, over the 50 byte limit".to_string()
+                ),
+            ]
+        );
+
+        let (assets, _) = domain.read(100, 1000).await;
+        assert_eq!(assets.len(), 4);
+    }
+
+    #[tokio::test]
+    async fn a_new_domain_has_no_files() {
+        let domain = Domain::new("missing", &[]);
+        assert_eq!(domain.read(1024, 1024).await, (Vec::new(), Vec::new()));
     }
 }
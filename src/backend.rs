@@ -0,0 +1,214 @@
+//! Pluggable LLM backends.
+//!
+//! Every backend turns a list of chat messages into a stream of [`CompletionEvent`]s. What the
+//! deltas contain (`<_out>` tags and all) is the model's business; the backend only deals with
+//! the wire format of its API.
+use futures_util::TryStreamExt;
+use futures_util::future::BoxFuture;
+use futures_util::stream::{BoxStream, Stream};
+use reqwest::Response;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::{fmt, io};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, Lines};
+use tokio_util::io::StreamReader;
+
+mod anthropic;
+mod mock;
+mod ollama;
+mod openai;
+
+pub use anthropic::AnthropicBackend;
+pub use mock::{MockBackend, MockConfig};
+pub use ollama::OllamaBackend;
+pub use openai::OpenAiBackend;
+
+pub type EventStream = BoxStream<'static, io::Result<CompletionEvent>>;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CompletionEvent {
+    Delta(String),
+    Finish(FinishReason),
+    Usage(Usage),
+    /// An error reported by the upstream inside an otherwise successful response.
+    Error(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FinishReason {
+    /// The model ended its turn on its own.
+    Stop,
+    /// The token limit was hit, so the output is truncated.
+    Length,
+    ContentFilter,
+    Other(String),
+}
+
+impl FinishReason {
+    /// Normalizes the reasons used by the different APIs.
+    pub fn parse(reason: &str) -> Self {
+        match reason {
+            "stop" | "end_turn" | "stop_sequence" => Self::Stop,
+            "length" | "max_tokens" => Self::Length,
+            "content_filter" | "refusal" => Self::ContentFilter,
+            other => Self::Other(other.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for FinishReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Stop => f.write_str("stop"),
+            Self::Length => f.write_str("length"),
+            Self::ContentFilter => f.write_str("content_filter"),
+            Self::Other(other) => f.write_str(other),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Usage {
+    pub prompt_tokens: Option<u64>,
+    pub completion_tokens: Option<u64>,
+}
+
+impl Usage {
+    /// Combines usage reported in parts, like Anthropic's input tokens at the start of a message
+    /// and output tokens at its end. Later counts win.
+    pub fn merge(self, later: Usage) -> Self {
+        Self {
+            prompt_tokens: later.prompt_tokens.or(self.prompt_tokens),
+            completion_tokens: later.completion_tokens.or(self.completion_tokens),
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct ChatCompletionMessage {
+    pub role: String,
+    pub content: String,
+}
+
+/// Per-request settings, overriding the backend's defaults where set.
+#[derive(Debug, Clone, Default)]
+pub struct RequestOptions {
+    pub model: Option<String>,
+    pub temperature: Option<f32>,
+    pub max_tokens: Option<u32>,
+}
+
+pub trait LlmBackend: Send + Sync {
+    /// Short name used in logs, e.g. `openai`.
+    fn name(&self) -> &'static str;
+
+    /// The model requests are sent to, if the backend names one.
+    fn model(&self) -> Option<&str>;
+
+    /// Starts a streaming completion. Resolves once the upstream has accepted the request.
+    fn stream<'a>(
+        &'a self,
+        messages: &'a [ChatCompletionMessage],
+        options: &'a RequestOptions,
+    ) -> BoxFuture<'a, io::Result<EventStream>>;
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BackendKind {
+    /// Any OpenAI-compatible endpoint. Without a URL this is the Hack Club endpoint the server
+    /// has always used.
+    #[default]
+    OpenAi,
+    Ollama,
+    Anthropic,
+    Mock,
+}
+
+impl FromStr for BackendKind {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, String> {
+        Ok(match name {
+            "openai" => BackendKind::OpenAi,
+            "ollama" => BackendKind::Ollama,
+            "anthropic" => BackendKind::Anthropic,
+            "mock" => BackendKind::Mock,
+            other => {
+                return Err(format!(
+                    "unknown backend `{other}`, expected one of: openai, ollama, anthropic, mock"
+                ));
+            }
+        })
+    }
+}
+
+/// The `[backend]` section of the config, and `BACKEND`, `BACKEND_URL`, `MODEL`, `API_KEY` and
+/// `MAX_TOKENS`.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BackendConfig {
+    #[serde(deserialize_with = "crate::config::parse")]
+    pub kind: BackendKind,
+    #[serde(rename = "url")]
+    pub base_url: Option<String>,
+    pub model: Option<String>,
+    pub api_key: Option<String>,
+    pub max_tokens: Option<u32>,
+    /// Only used by the mock backend.
+    pub mock: MockConfig,
+}
+
+impl BackendConfig {
+    pub fn build(self) -> Result<Arc<dyn LlmBackend>, String> {
+        let client = reqwest::Client::new();
+
+        Ok(match self.kind {
+            BackendKind::OpenAi => Arc::new(OpenAiBackend::new(
+                client,
+                self.base_url,
+                self.model,
+                self.api_key,
+            )),
+            BackendKind::Ollama => Arc::new(OllamaBackend::new(
+                client,
+                self.base_url,
+                self.model
+                    .ok_or("the ollama backend requires MODEL to be set")?,
+            )),
+            BackendKind::Anthropic => Arc::new(AnthropicBackend::new(
+                client,
+                self.base_url,
+                self.model
+                    .ok_or("the anthropic backend requires MODEL to be set")?,
+                self.api_key
+                    .ok_or("the anthropic backend requires API_KEY to be set")?,
+                self.max_tokens,
+            )),
+            BackendKind::Mock => Arc::new(MockBackend::new(self.mock)),
+        })
+    }
+}
+
+/// Fails on non-2xx statuses, including the upstream's error body in the message.
+async fn check_status(resp: Response) -> io::Result<Response> {
+    let status = resp.status();
+    if status.is_success() {
+        return Ok(resp);
+    }
+
+    let body = resp.text().await.unwrap_or_default();
+    Err(io::Error::other(format!(
+        "upstream returned {status}: {body}"
+    )))
+}
+
+fn body(
+    resp: Response,
+) -> impl Stream<Item = io::Result<impl AsRef<[u8]> + Send>> + Send + 'static {
+    resp.bytes_stream().map_err(io::Error::other)
+}
+
+fn lines(resp: Response) -> Lines<impl AsyncBufRead + Send + Unpin> {
+    StreamReader::new(resp.bytes_stream().map_err(io::Error::other)).lines()
+}
@@ -0,0 +1,287 @@
+//! Anthropic-style `/v1/messages` API. The system prompt is a top-level field rather than a
+//! message, and text arrives as `content_block_delta` events.
+use async_stream::try_stream;
+use futures_util::StreamExt;
+use futures_util::future::BoxFuture;
+use futures_util::stream::BoxStream;
+use reqwest::{Client, header::CONTENT_TYPE};
+use serde::{Deserialize, Serialize};
+use std::io;
+
+use super::{
+    ChatCompletionMessage, CompletionEvent, EventStream, FinishReason, LlmBackend, RequestOptions,
+    Usage, body, check_status,
+};
+use crate::sse::{self, SseEvent};
+
+const DEFAULT_BASE_URL: &str = "https://api.anthropic.com";
+const API_VERSION: &str = "2023-06-01";
+// The messages API refuses requests without `max_tokens`
+const DEFAULT_MAX_TOKENS: u32 = 16384;
+
+#[derive(Serialize)]
+struct MessagesRequest<'a> {
+    model: &'a str,
+    max_tokens: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    system: Option<&'a str>,
+    messages: Vec<&'a ChatCompletionMessage>,
+    stream: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum StreamEvent {
+    MessageStart {
+        message: StartMessage,
+    },
+    ContentBlockDelta {
+        delta: BlockDelta,
+    },
+    MessageDelta {
+        delta: MessageDelta,
+        usage: Option<ApiUsage>,
+    },
+    MessageStop,
+    Error {
+        error: ApiError,
+    },
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Deserialize)]
+struct StartMessage {
+    usage: Option<ApiUsage>,
+}
+
+#[derive(Deserialize)]
+struct BlockDelta {
+    text: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct MessageDelta {
+    stop_reason: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ApiUsage {
+    input_tokens: Option<u64>,
+    output_tokens: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct ApiError {
+    message: String,
+}
+
+pub struct AnthropicBackend {
+    client: Client,
+    messages: String,
+    model: String,
+    api_key: String,
+    max_tokens: u32,
+}
+
+impl AnthropicBackend {
+    pub fn new(
+        client: Client,
+        base_url: Option<String>,
+        model: String,
+        api_key: String,
+        max_tokens: Option<u32>,
+    ) -> Self {
+        let base_url = base_url.unwrap_or_else(|| DEFAULT_BASE_URL.to_string());
+
+        Self {
+            client,
+            messages: format!("{}/v1/messages", base_url.trim_end_matches('/')),
+            model,
+            api_key,
+            max_tokens: max_tokens.unwrap_or(DEFAULT_MAX_TOKENS),
+        }
+    }
+}
+
+impl LlmBackend for AnthropicBackend {
+    fn name(&self) -> &'static str {
+        "anthropic"
+    }
+
+    fn model(&self) -> Option<&str> {
+        Some(&self.model)
+    }
+
+    fn stream<'a>(
+        &'a self,
+        messages: &'a [ChatCompletionMessage],
+        options: &'a RequestOptions,
+    ) -> BoxFuture<'a, io::Result<EventStream>> {
+        Box::pin(async move {
+            let system = messages
+                .iter()
+                .find(|m| m.role == "system")
+                .map(|m| m.content.as_str());
+
+            let request = MessagesRequest {
+                model: options.model.as_deref().unwrap_or(&self.model),
+                max_tokens: options.max_tokens.unwrap_or(self.max_tokens),
+                temperature: options.temperature,
+                system,
+                messages: messages.iter().filter(|m| m.role != "system").collect(),
+                stream: true,
+            };
+
+            let resp = self
+                .client
+                .post(&self.messages)
+                .header(CONTENT_TYPE, "application/json")
+                .header("x-api-key", &self.api_key)
+                .header("anthropic-version", API_VERSION)
+                .json(&request)
+                .send()
+                .await
+                .map_err(io::Error::other)?;
+
+            Ok(decode(sse::decode(body(check_status(resp).await?))))
+        })
+    }
+}
+
+/// Decodes the events of a `/v1/messages` SSE body.
+fn decode(mut events: BoxStream<'static, io::Result<SseEvent>>) -> EventStream {
+    Box::pin(try_stream! {
+        while let Some(event) = events.next().await {
+            let data = event?.data;
+            let event = match serde_json::from_str::<StreamEvent>(&data) {
+                Ok(event) => event,
+                Err(e) => {
+                    yield CompletionEvent::Error(format!("malformed event ({e}): {data}"));
+                    continue;
+                }
+            };
+
+            match event {
+                StreamEvent::MessageStart { message } => {
+                    if let Some(usage) = message.usage {
+                        yield CompletionEvent::Usage(usage.into());
+                    }
+                }
+                StreamEvent::ContentBlockDelta { delta } => {
+                    if let Some(text) = delta.text {
+                        yield CompletionEvent::Delta(text);
+                    }
+                }
+                StreamEvent::MessageDelta { delta, usage } => {
+                    if let Some(usage) = usage {
+                        yield CompletionEvent::Usage(usage.into());
+                    }
+                    if let Some(reason) = delta.stop_reason {
+                        yield CompletionEvent::Finish(FinishReason::parse(&reason));
+                    }
+                }
+                StreamEvent::MessageStop => break,
+                StreamEvent::Error { error } => yield CompletionEvent::Error(error.message),
+                StreamEvent::Other => {}
+            }
+        }
+    })
+}
+
+impl From<ApiUsage> for Usage {
+    fn from(usage: ApiUsage) -> Self {
+        Self {
+            prompt_tokens: usage.input_tokens,
+            completion_tokens: usage.output_tokens,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn completion(body: &str) -> Vec<CompletionEvent> {
+        let chunks: Vec<io::Result<String>> = vec![Ok(body.to_string())];
+        decode(sse::decode(futures_util::stream::iter(chunks)))
+            .map(Result::unwrap)
+            .collect()
+            .await
+    }
+
+    #[tokio::test]
+    async fn content_usage_and_stop() {
+        let events = completion(concat!(
+            "event: message_start\n",
+            "data: {\"type\":\"message_start\",\"message\":{\"usage\":{\"input_tokens\":25,\"output_tokens\":1}}}\n\n",
+            "event: content_block_start\n",
+            "data: {\"type\":\"content_block_start\",\"index\":0,\"content_block\":{\"type\":\"text\",\"text\":\"\"}}\n\n",
+            "event: ping\ndata: {\"type\":\"ping\"}\n\n",
+            "event: content_block_delta\n",
+            "data: {\"type\":\"content_block_delta\",\"index\":0,\"delta\":{\"type\":\"text_delta\",\"text\":\"<_out>hi\"}}\n\n",
+            "event: content_block_delta\n",
+            "data: {\"type\":\"content_block_delta\",\"index\":0,\"delta\":{\"type\":\"text_delta\",\"text\":\"</_out>\"}}\n\n",
+            "event: content_block_stop\ndata: {\"type\":\"content_block_stop\",\"index\":0}\n\n",
+            "event: message_delta\n",
+            "data: {\"type\":\"message_delta\",\"delta\":{\"stop_reason\":\"end_turn\"},\"usage\":{\"output_tokens\":15}}\n\n",
+            "event: message_stop\ndata: {\"type\":\"message_stop\"}\n\n",
+            "data: {\"type\":\"content_block_delta\",\"delta\":{\"text\":\"after the end\"}}\n\n",
+        ))
+        .await;
+
+        assert_eq!(
+            events,
+            [
+                CompletionEvent::Usage(Usage {
+                    prompt_tokens: Some(25),
+                    completion_tokens: Some(1),
+                }),
+                CompletionEvent::Delta("<_out>hi".into()),
+                CompletionEvent::Delta("</_out>".into()),
+                CompletionEvent::Usage(Usage {
+                    prompt_tokens: None,
+                    completion_tokens: Some(15),
+                }),
+                CompletionEvent::Finish(FinishReason::Stop),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn stop_reasons_are_normalized() {
+        for (reason, expected) in [
+            ("end_turn", FinishReason::Stop),
+            ("stop_sequence", FinishReason::Stop),
+            ("max_tokens", FinishReason::Length),
+            ("refusal", FinishReason::ContentFilter),
+        ] {
+            let events = completion(&format!(
+                "data: {{\"type\":\"message_delta\",\"delta\":{{\"stop_reason\":\"{reason}\"}}}}\n\n"
+            ))
+            .await;
+            assert_eq!(events, [CompletionEvent::Finish(expected)], "{reason}");
+        }
+    }
+
+    #[tokio::test]
+    async fn errors() {
+        let events = completion(concat!(
+            "event: error\n",
+            "data: {\"type\":\"error\",\"error\":{\"type\":\"overloaded_error\",\"message\":\"Overloaded\"}}\n\n",
+            "data: {not json\n\n",
+            "data: {\"type\":\"content_block_delta\",\"delta\":{\"text\":\"still going\"}}\n\n",
+        ))
+        .await;
+
+        assert_eq!(events[0], CompletionEvent::Error("Overloaded".into()));
+        assert!(
+            matches!(&events[1], CompletionEvent::Error(e) if e.starts_with("malformed event")),
+            "{:?}",
+            events[1]
+        );
+        assert_eq!(events[2], CompletionEvent::Delta("still going".into()));
+    }
+}
@@ -0,0 +1,203 @@
+//! A deterministic, offline backend for development and tests.
+//!
+//! It answers every request with a canned `<_out>` response, encoded as the same
+//! `/chat/completions` SSE chunks the OpenAI backend decodes, so everything downstream of the
+//! HTTP request is exercised for real.
+use async_stream::stream;
+use futures_util::future::BoxFuture;
+use serde::{Deserialize, Deserializer};
+use serde_json::json;
+use std::io;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
+use super::{ChatCompletionMessage, EventStream, LlmBackend, RequestOptions, openai};
+use crate::sse;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MockFailure {
+    /// `stream` itself fails, as if the upstream refused the connection.
+    Connect,
+    /// The body errors out after `fail_after` chunks, as if the connection dropped.
+    Midstream,
+    /// The body ends cleanly after `fail_after` chunks, without the rest of the response.
+    Truncate,
+    /// An error payload is sent after `fail_after` chunks, as rate limiters mid-stream do.
+    Error,
+}
+
+impl FromStr for MockFailure {
+    type Err = String;
+
+    fn from_str(failure: &str) -> Result<Self, String> {
+        Ok(match failure {
+            "connect" => Self::Connect,
+            "midstream" => Self::Midstream,
+            "truncate" => Self::Truncate,
+            "error" => Self::Error,
+            other => {
+                return Err(format!(
+                    "unknown failure `{other}`, expected one of: connect, midstream, truncate, error"
+                ));
+            }
+        })
+    }
+}
+
+/// The `[backend.mock]` section of the config, and the `MOCK_*` variables.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MockConfig {
+    /// File whose contents are sent verbatim instead of the generated placeholder.
+    pub response: Option<PathBuf>,
+    pub chunk_size: usize,
+    /// Delay before each chunk.
+    pub latency_ms: u64,
+    #[serde(rename = "fail", deserialize_with = "parse_failure")]
+    pub failure: Option<MockFailure>,
+    /// Defaults to half of the chunks.
+    pub fail_after: Option<usize>,
+}
+
+impl Default for MockConfig {
+    fn default() -> Self {
+        Self {
+            response: None,
+            chunk_size: 16,
+            latency_ms: 0,
+            failure: None,
+            fail_after: None,
+        }
+    }
+}
+
+fn parse_failure<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<MockFailure>, D::Error> {
+    crate::config::parse(deserializer).map(Some)
+}
+
+pub struct MockBackend {
+    config: MockConfig,
+}
+
+impl MockBackend {
+    pub fn new(config: MockConfig) -> Self {
+        Self { config }
+    }
+
+    async fn response(&self, messages: &[ChatCompletionMessage]) -> io::Result<String> {
+        if let Some(path) = &self.config.response {
+            return tokio::fs::read_to_string(path).await;
+        }
+
+        let url = messages
+            .iter()
+            .filter(|m| m.role == "user")
+            .find_map(|m| m.content.lines().next()?.strip_prefix("URL to create: "))
+            .unwrap_or("unknown");
+
+        Ok(placeholder(url))
+    }
+}
+
+impl LlmBackend for MockBackend {
+    fn name(&self) -> &'static str {
+        "mock"
+    }
+
+    fn model(&self) -> Option<&str> {
+        Some("mock")
+    }
+
+    fn stream<'a>(
+        &'a self,
+        messages: &'a [ChatCompletionMessage],
+        _options: &'a RequestOptions,
+    ) -> BoxFuture<'a, io::Result<EventStream>> {
+        Box::pin(async move {
+            if self.config.failure == Some(MockFailure::Connect) {
+                return Err(io::Error::new(
+                    io::ErrorKind::ConnectionRefused,
+                    "mock backend: injected connect failure",
+                ));
+            }
+
+            let response = self.response(messages).await?;
+
+            // Roughly four characters per token, like most tokenizers on English text
+            let prompt_tokens = messages.iter().map(|m| m.content.len()).sum::<usize>() / 4;
+            let completion_tokens = response.len() / 4;
+
+            let chunks = split(&response, self.config.chunk_size);
+            let fail_after = self.config.fail_after.unwrap_or(chunks.len() / 2);
+            let failure = self.config.failure;
+            let latency = Duration::from_millis(self.config.latency_ms);
+
+            let body = stream! {
+                for (i, chunk) in chunks.into_iter().enumerate() {
+                    if i == fail_after {
+                        match failure {
+                            Some(MockFailure::Midstream) => {
+                                yield Err(io::Error::new(
+                                    io::ErrorKind::ConnectionReset,
+                                    "mock backend: injected midstream failure",
+                                ));
+                                return;
+                            }
+                            Some(MockFailure::Truncate) => return,
+                            Some(MockFailure::Error) => {
+                                let event = json!({ "error": { "message": "mock backend: injected error" } });
+                                yield Ok(format!("data: {event}\n\n").into_bytes());
+                                return;
+                            }
+                            _ => {}
+                        }
+                    }
+
+                    if !latency.is_zero() {
+                        tokio::time::sleep(latency).await;
+                    }
+
+                    let event = json!({ "choices": [{ "delta": { "content": chunk } }] });
+                    yield Ok(format!("data: {event}\n\n").into_bytes());
+                }
+
+                let event = json!({
+                    "choices": [{ "delta": {}, "finish_reason": "stop" }],
+                    "usage": { "prompt_tokens": prompt_tokens, "completion_tokens": completion_tokens },
+                });
+                yield Ok(format!("data: {event}\n\ndata: [DONE]\n\n").into_bytes());
+            };
+
+            Ok(openai::decode(sse::decode(body)))
+        })
+    }
+}
+
+/// Splits on char boundaries so multi-byte characters survive.
+fn split(response: &str, chunk_size: usize) -> Vec<String> {
+    let chars: Vec<char> = response.chars().collect();
+    chars
+        .chunks(chunk_size)
+        .map(|chunk| chunk.iter().collect())
+        .collect()
+}
+
+fn placeholder(url: &str) -> String {
+    let extension = url.rsplit_once('.').map(|(_, ext)| ext).unwrap_or("html");
+
+    let content = match extension {
+        "css" => "body { font-family: sans-serif; }".to_string(),
+        "js" => format!("console.log({:?});", url),
+        "json" => json!({ "url": url }).to_string(),
+        "svg" => r#"<svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 1 1"><rect width="1" height="1"/></svg>"#.to_string(),
+        "txt" | "md" => format!("Mock content for {url}"),
+        _ => format!(
+            "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n  <meta charset=\"UTF-8\">\n  <title>{url}</title>\n</head>\n<body>\n  <h1>{url}</h1>\n  <p>Generated by the mock backend.</p>\n</body>\n</html>"
+        ),
+    };
+
+    format!("<_out>\n{content}\n</_out>")
+}
@@ -0,0 +1,219 @@
+//! A local Ollama (or llama.cpp in Ollama mode) server using the native `/api/chat` endpoint,
+//! which streams newline-delimited JSON instead of SSE.
+use async_stream::try_stream;
+use futures_util::future::BoxFuture;
+use reqwest::{Client, header::CONTENT_TYPE};
+use serde::{Deserialize, Serialize};
+use std::io;
+use tokio::io::{AsyncBufRead, Lines};
+
+use super::{
+    ChatCompletionMessage, CompletionEvent, EventStream, FinishReason, LlmBackend, RequestOptions,
+    Usage, check_status, lines,
+};
+
+const DEFAULT_BASE_URL: &str = "http://localhost:11434";
+
+#[derive(Serialize)]
+struct ChatRequest<'a> {
+    model: &'a str,
+    messages: &'a [ChatCompletionMessage],
+    stream: bool,
+    options: ModelOptions,
+}
+
+#[derive(Serialize)]
+struct ModelOptions {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    num_predict: Option<u32>,
+}
+
+#[derive(Deserialize)]
+struct ChatChunk {
+    message: Option<ChunkMessage>,
+    error: Option<String>,
+    #[serde(default)]
+    done: bool,
+    done_reason: Option<String>,
+    prompt_eval_count: Option<u64>,
+    eval_count: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct ChunkMessage {
+    content: String,
+}
+
+pub struct OllamaBackend {
+    client: Client,
+    chat: String,
+    model: String,
+}
+
+impl OllamaBackend {
+    pub fn new(client: Client, base_url: Option<String>, model: String) -> Self {
+        let base_url = base_url.unwrap_or_else(|| DEFAULT_BASE_URL.to_string());
+
+        Self {
+            client,
+            chat: format!("{}/api/chat", base_url.trim_end_matches('/')),
+            model,
+        }
+    }
+}
+
+impl LlmBackend for OllamaBackend {
+    fn name(&self) -> &'static str {
+        "ollama"
+    }
+
+    fn model(&self) -> Option<&str> {
+        Some(&self.model)
+    }
+
+    fn stream<'a>(
+        &'a self,
+        messages: &'a [ChatCompletionMessage],
+        options: &'a RequestOptions,
+    ) -> BoxFuture<'a, io::Result<EventStream>> {
+        Box::pin(async move {
+            let request = ChatRequest {
+                model: options.model.as_deref().unwrap_or(&self.model),
+                messages,
+                stream: true,
+                options: ModelOptions {
+                    temperature: options.temperature,
+                    num_predict: options.max_tokens,
+                },
+            };
+
+            let resp = self
+                .client
+                .post(&self.chat)
+                .header(CONTENT_TYPE, "application/json")
+                .json(&request)
+                .send()
+                .await
+                .map_err(io::Error::other)?;
+
+            Ok(decode(lines(check_status(resp).await?)))
+        })
+    }
+}
+
+/// Decodes the chunks of an `/api/chat` body, one JSON object per line.
+fn decode(mut lines: Lines<impl AsyncBufRead + Send + Unpin + 'static>) -> EventStream {
+    Box::pin(try_stream! {
+        while let Some(line) = lines.next_line().await? {
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let chunk = match serde_json::from_str::<ChatChunk>(&line) {
+                Ok(chunk) => chunk,
+                Err(e) => {
+                    yield CompletionEvent::Error(format!("malformed chunk ({e}): {line}"));
+                    continue;
+                }
+            };
+
+            if let Some(error) = chunk.error {
+                yield CompletionEvent::Error(error);
+                continue;
+            }
+
+            if let Some(message) = chunk.message
+                && !message.content.is_empty()
+            {
+                yield CompletionEvent::Delta(message.content);
+            }
+
+            if chunk.done {
+                yield CompletionEvent::Usage(Usage {
+                    prompt_tokens: chunk.prompt_eval_count,
+                    completion_tokens: chunk.eval_count,
+                });
+
+                // Older servers do not send a reason, but `done` alone means it stopped
+                let reason = chunk.done_reason.as_deref().unwrap_or("stop");
+                yield CompletionEvent::Finish(FinishReason::parse(reason));
+                break;
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::StreamExt;
+    use tokio::io::AsyncBufReadExt;
+
+    async fn completion(body: &'static str) -> Vec<CompletionEvent> {
+        decode(tokio::io::BufReader::new(body.as_bytes()).lines())
+            .map(Result::unwrap)
+            .collect()
+            .await
+    }
+
+    #[tokio::test]
+    async fn content_usage_and_stop() {
+        let events = completion(concat!(
+            "{\"message\":{\"role\":\"assistant\",\"content\":\"<_out>hi\"},\"done\":false}\n",
+            "\n",
+            "{\"message\":{\"role\":\"assistant\",\"content\":\"\"},\"done\":false}\n",
+            "{\"message\":{\"role\":\"assistant\",\"content\":\"</_out>\"},\"done\":false}\n",
+            "{\"message\":{\"role\":\"assistant\",\"content\":\"\"},\"done\":true,\"done_reason\":\"stop\",\"prompt_eval_count\":26,\"eval_count\":3}\n",
+            "{\"message\":{\"role\":\"assistant\",\"content\":\"after the end\"},\"done\":false}\n",
+        ))
+        .await;
+
+        assert_eq!(
+            events,
+            [
+                CompletionEvent::Delta("<_out>hi".into()),
+                CompletionEvent::Delta("</_out>".into()),
+                CompletionEvent::Usage(Usage {
+                    prompt_tokens: Some(26),
+                    completion_tokens: Some(3),
+                }),
+                CompletionEvent::Finish(FinishReason::Stop),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn stop_reasons() {
+        // Older servers only say they are done
+        let events = completion("{\"done\":true}\n").await;
+        assert_eq!(events[1], CompletionEvent::Finish(FinishReason::Stop));
+
+        let events = completion("{\"done\":true,\"done_reason\":\"length\"}\n").await;
+        assert_eq!(events[1], CompletionEvent::Finish(FinishReason::Length));
+    }
+
+    #[tokio::test]
+    async fn errors() {
+        let events = completion(concat!(
+            "{\"error\":\"model 'x' not found\"}\n",
+            "{not json\n",
+            "{\"message\":{\"content\":\"still going\"}}\n",
+        ))
+        .await;
+
+        assert_eq!(
+            events[0],
+            CompletionEvent::Error("model 'x' not found".into())
+        );
+        assert!(
+            matches!(&events[1], CompletionEvent::Error(e) if e.starts_with("malformed chunk")),
+            "{:?}",
+            events[1]
+        );
+        assert_eq!(events[2], CompletionEvent::Delta("still going".into()));
+        // Ending without `done` is left for the caller to notice
+        assert_eq!(events.len(), 3);
+    }
+}
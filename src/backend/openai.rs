@@ -0,0 +1,298 @@
+//! Any OpenAI-compatible `/chat/completions` endpoint. This is also what ai.hackclub.com speaks.
+use async_stream::try_stream;
+use futures_util::StreamExt;
+use futures_util::future::BoxFuture;
+use futures_util::stream::BoxStream;
+use reqwest::{Client, header::CONTENT_TYPE};
+use serde::{Deserialize, Serialize};
+use std::io;
+
+use super::{
+    ChatCompletionMessage, CompletionEvent, EventStream, FinishReason, LlmBackend, RequestOptions,
+    Usage, body, check_status,
+};
+use crate::sse::{self, SseEvent};
+
+const DEFAULT_BASE_URL: &str = "https://ai.hackclub.com";
+
+// Response
+#[derive(Debug, Deserialize)]
+pub struct AIResponse {
+    #[serde(default)]
+    pub choices: Vec<Choice>,
+    pub usage: Option<ApiUsage>,
+    pub error: Option<serde_json::Value>,
+    //pub created: u64,
+    //pub id: String,
+    //pub model: String,
+    //pub object: String,
+    //#[serde(rename = "system_fingerprint")]
+    //pub system_fingerprint: String,
+    #[serde(rename = "x_groq")]
+    pub x_groq: Option<XGroq>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Choice {
+    pub delta: Option<Delta>,
+    pub finish_reason: Option<String>,
+    // pub message: Option<ChatCompletionMessage>,
+    //pub index: u32,
+    //pub logprobs: Option<serde_json::Value>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Delta {
+    pub content: Option<String>,
+    // pub role: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ApiUsage {
+    pub prompt_tokens: Option<u64>,
+    pub completion_tokens: Option<u64>,
+}
+
+/// Groq reports usage here instead of at the top level.
+#[derive(Debug, Deserialize)]
+pub struct XGroq {
+    pub usage: Option<ApiUsage>,
+}
+
+// Request
+#[derive(Serialize, Debug, Clone)]
+struct RequestPayload<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    model: Option<&'a str>,
+    messages: &'a [ChatCompletionMessage],
+    stream: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_tokens: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    include_reasoning: Option<bool>,
+}
+
+pub struct OpenAiBackend {
+    client: Client,
+    completions: String,
+    model: Option<String>,
+    api_key: Option<String>,
+    include_reasoning: Option<bool>,
+}
+
+impl OpenAiBackend {
+    /// `base_url` is everything before `/chat/completions`, e.g. `https://api.openai.com/v1`.
+    pub fn new(
+        client: Client,
+        base_url: Option<String>,
+        model: Option<String>,
+        api_key: Option<String>,
+    ) -> Self {
+        // `include_reasoning` is a Hack Club extension; real OpenAI rejects unknown fields.
+        let (base_url, include_reasoning) = match base_url {
+            Some(url) => (url, None),
+            None => (DEFAULT_BASE_URL.to_string(), Some(false)),
+        };
+
+        Self {
+            client,
+            completions: format!("{}/chat/completions", base_url.trim_end_matches('/')),
+            model,
+            api_key,
+            include_reasoning,
+        }
+    }
+}
+
+impl LlmBackend for OpenAiBackend {
+    fn name(&self) -> &'static str {
+        "openai"
+    }
+
+    fn model(&self) -> Option<&str> {
+        self.model.as_deref()
+    }
+
+    fn stream<'a>(
+        &'a self,
+        messages: &'a [ChatCompletionMessage],
+        options: &'a RequestOptions,
+    ) -> BoxFuture<'a, io::Result<EventStream>> {
+        Box::pin(async move {
+            let request = RequestPayload {
+                model: options.model.as_deref().or(self.model.as_deref()),
+                messages,
+                stream: true,
+                temperature: options.temperature,
+                max_tokens: options.max_tokens,
+                include_reasoning: self.include_reasoning,
+            };
+
+            let mut builder = self
+                .client
+                .post(&self.completions)
+                .header(CONTENT_TYPE, "application/json")
+                .json(&request);
+
+            if let Some(key) = &self.api_key {
+                builder = builder.bearer_auth(key);
+            }
+
+            let resp = builder.send().await.map_err(io::Error::other)?;
+            Ok(decode(sse::decode(body(check_status(resp).await?))))
+        })
+    }
+}
+
+/// Decodes the events of a `/chat/completions` SSE body.
+pub(super) fn decode(mut events: BoxStream<'static, io::Result<SseEvent>>) -> EventStream {
+    Box::pin(try_stream! {
+        while let Some(event) = events.next().await {
+            let event = event?;
+
+            if event.data == "[DONE]" {
+                break;
+            }
+
+            if event.event.as_deref() == Some("error") {
+                yield CompletionEvent::Error(event.data);
+                continue;
+            }
+
+            let json = match serde_json::from_str::<AIResponse>(&event.data) {
+                Ok(json) => json,
+                Err(e) => {
+                    yield CompletionEvent::Error(format!("malformed chunk ({e}): {}", event.data));
+                    continue;
+                }
+            };
+
+            if let Some(error) = json.error {
+                let message = error["message"].as_str().map(str::to_string);
+                yield CompletionEvent::Error(message.unwrap_or_else(|| error.to_string()));
+                continue;
+            }
+
+            if let Some(choice) = json.choices.into_iter().next() {
+                if let Some(content) = choice.delta.and_then(|delta| delta.content) {
+                    yield CompletionEvent::Delta(content);
+                }
+
+                if let Some(reason) = choice.finish_reason {
+                    yield CompletionEvent::Finish(FinishReason::parse(&reason));
+                }
+            }
+
+            if let Some(usage) = json.usage.or(json.x_groq.and_then(|x| x.usage)) {
+                yield CompletionEvent::Usage(Usage {
+                    prompt_tokens: usage.prompt_tokens,
+                    completion_tokens: usage.completion_tokens,
+                });
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn completion(body: &str) -> Vec<CompletionEvent> {
+        let chunks: Vec<io::Result<String>> = vec![Ok(body.to_string())];
+        decode(sse::decode(futures_util::stream::iter(chunks)))
+            .map(Result::unwrap)
+            .collect()
+            .await
+    }
+
+    #[tokio::test]
+    async fn deltas_finish_and_usage() {
+        let events = completion(concat!(
+            "data: {\"choices\":[{\"delta\":{\"role\":\"assistant\"}}]}\n\n",
+            "data: {\"choices\":[{\"delta\":{\"content\":\"<_out>\"}}]}\n\n",
+            "data: {\"choices\":[{\"delta\":{\"content\":\"hi</_out>\"},\"finish_reason\":\"stop\"}]}\n\n",
+            "data: {\"choices\":[],\"usage\":{\"prompt_tokens\":12,\"completion_tokens\":3}}\n\n",
+            "data: [DONE]\n\n",
+        ))
+        .await;
+
+        assert_eq!(
+            events,
+            [
+                CompletionEvent::Delta("<_out>".into()),
+                CompletionEvent::Delta("hi</_out>".into()),
+                CompletionEvent::Finish(FinishReason::Stop),
+                CompletionEvent::Usage(Usage {
+                    prompt_tokens: Some(12),
+                    completion_tokens: Some(3),
+                }),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn finish_reasons_are_normalized() {
+        for (reason, expected) in [
+            ("stop", FinishReason::Stop),
+            ("length", FinishReason::Length),
+            ("content_filter", FinishReason::ContentFilter),
+            ("tool_calls", FinishReason::Other("tool_calls".into())),
+        ] {
+            let events = completion(&format!(
+                "data: {{\"choices\":[{{\"delta\":{{}},\"finish_reason\":\"{reason}\"}}]}}\n\n"
+            ))
+            .await;
+            assert_eq!(events, [CompletionEvent::Finish(expected)], "{reason}");
+        }
+    }
+
+    #[tokio::test]
+    async fn groq_usage() {
+        let events = completion(
+            "data: {\"choices\":[],\"x_groq\":{\"usage\":{\"prompt_tokens\":5,\"completion_tokens\":7}}}\n\n",
+        )
+        .await;
+
+        assert_eq!(
+            events,
+            [CompletionEvent::Usage(Usage {
+                prompt_tokens: Some(5),
+                completion_tokens: Some(7),
+            })]
+        );
+    }
+
+    #[tokio::test]
+    async fn nothing_after_done() {
+        let events = completion(concat!(
+            "data: {\"choices\":[{\"delta\":{\"content\":\"a\"}}]}\n\n",
+            "data: [DONE]\n\n",
+            "data: {\"choices\":[{\"delta\":{\"content\":\"b\"}}]}\n\n",
+        ))
+        .await;
+
+        assert_eq!(events, [CompletionEvent::Delta("a".into())]);
+    }
+
+    #[tokio::test]
+    async fn errors() {
+        let events = completion(concat!(
+            "event: error\ndata: overloaded\n\n",
+            "data: {\"error\":{\"message\":\"rate limited\",\"code\":429}}\n\n",
+            "data: {\"error\":\"bare\"}\n\n",
+            "data: {not json\n\n",
+        ))
+        .await;
+
+        assert_eq!(events[0], CompletionEvent::Error("overloaded".into()));
+        assert_eq!(events[1], CompletionEvent::Error("rate limited".into()));
+        assert_eq!(events[2], CompletionEvent::Error("\"bare\"".into()));
+        assert!(
+            matches!(&events[3], CompletionEvent::Error(e) if e.starts_with("malformed chunk")),
+            "{:?}",
+            events[3]
+        );
+    }
+}
@@ -0,0 +1,169 @@
+//! Browsable views of the index page: every generated domain, and the directory tree of one.
+//!
+//! Links point at the pages themselves, on the host of their domain with virtual hosts, so
+//! following them goes through `ServeDir` or generation like any other request.
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::time::SystemTime;
+use time::OffsetDateTime;
+
+use crate::meta::Metadata;
+use crate::search::{Document, SearchIndex};
+use crate::store::Store;
+use crate::vhost::{self, VirtualHosts};
+
+struct DomainSummary {
+    domain: String,
+    pages: usize,
+    last_modified: SystemTime,
+}
+
+#[derive(Default)]
+struct Directory {
+    directories: BTreeMap<String, Directory>,
+    files: BTreeMap<String, (Document, Option<Metadata>)>,
+}
+
+/// List items for every domain, most recently modified first.
+pub fn domains(search: &SearchIndex, vhosts: Option<&VirtualHosts>) -> String {
+    let mut domains: BTreeMap<String, DomainSummary> = BTreeMap::new();
+
+    for document in search.documents() {
+        let summary = domains
+            .entry(document.domain.clone())
+            .or_insert_with(|| DomainSummary {
+                domain: document.domain.clone(),
+                pages: 0,
+                last_modified: document.created,
+            });
+
+        summary.pages += 1;
+        summary.last_modified = summary.last_modified.max(document.created);
+    }
+
+    let mut domains: Vec<DomainSummary> = domains.into_values().collect();
+    domains.sort_by_key(|summary| Reverse(summary.last_modified));
+
+    domains
+        .iter()
+        .map(|summary| {
+            let domain = html_escape::encode_text(&summary.domain);
+            let href = html_escape::encode_double_quoted_attribute(&summary.domain);
+            let visit = vhost::link(vhosts, &summary.domain);
+            let visit = html_escape::encode_double_quoted_attribute(&visit);
+
+            format!(
+                r#"<li><a href="/?domain={href}">{domain}</a><p class="text-xs text-gray-500">{} · last modified {} · <a href="{visit}">visit</a></p></li>"#,
+                pages(summary.pages),
+                date(summary.last_modified),
+            )
+        })
+        .collect()
+}
+
+/// List items for the directory tree of `domain`, or `None` if nothing of it was generated.
+pub async fn tree(
+    search: &SearchIndex,
+    store: &Store,
+    vhosts: Option<&VirtualHosts>,
+    domain: &str,
+) -> Option<String> {
+    let mut root = Directory::default();
+
+    for document in search.documents() {
+        if !document.domain.eq_ignore_ascii_case(domain) {
+            continue;
+        }
+
+        let meta = store.current_metadata(&document.path).await;
+
+        let mut components: Vec<String> = document
+            .path
+            .split('/')
+            .skip(1)
+            .map(str::to_string)
+            .collect();
+        let Some(name) = components.pop() else {
+            continue;
+        };
+
+        let directory = components.into_iter().fold(&mut root, |dir, component| {
+            dir.directories.entry(component).or_default()
+        });
+
+        directory.files.insert(name, (document, meta));
+    }
+
+    if root.files.is_empty() && root.directories.is_empty() {
+        return None;
+    }
+
+    let mut html = String::new();
+    render(&root, vhosts, &mut html);
+    Some(html)
+}
+
+fn render(directory: &Directory, vhosts: Option<&VirtualHosts>, html: &mut String) {
+    for (name, child) in &directory.directories {
+        html.push_str(&format!(
+            r#"<li><details open><summary>{}/ <span class="text-xs text-gray-500">{}</span></summary><ul class="ml-4 mt-2 space-y-2">"#,
+            html_escape::encode_text(name),
+            pages(child.count()),
+        ));
+        render(child, vhosts, html);
+        html.push_str("</ul></details></li>");
+    }
+
+    for (name, (document, meta)) in &directory.files {
+        let path = html_escape::encode_double_quoted_attribute(&document.path);
+        let href = vhost::link(vhosts, &document.path);
+        let href = html_escape::encode_double_quoted_attribute(&href);
+
+        let details = match meta {
+            Some(meta) => summary(meta, &path),
+            None => format!(
+                r#"<p class="text-xs text-gray-500">{}</p>"#,
+                date(document.created)
+            ),
+        };
+
+        html.push_str(&format!(
+            r#"<li><a href="{href}">{}</a>{details}</li>"#,
+            html_escape::encode_text(name),
+        ));
+    }
+}
+
+/// The provenance line shown under a page.
+fn summary(meta: &Metadata, path: &str) -> String {
+    format!(
+        r#"<p class="text-xs text-gray-500">{} · {} · {} tokens · {:.1}s · <a href="/_meta/{path}">meta</a></p>"#,
+        html_escape::encode_text(meta.model.as_deref().unwrap_or(&meta.backend)),
+        meta.created.get(..10).unwrap_or(&meta.created),
+        meta.completion_tokens
+            .map_or("?".to_string(), |t| t.to_string()),
+        meta.latency_ms as f64 / 1000.0,
+    )
+}
+
+impl Directory {
+    fn count(&self) -> usize {
+        self.files.len()
+            + self
+                .directories
+                .values()
+                .map(Directory::count)
+                .sum::<usize>()
+    }
+}
+
+fn date(time: SystemTime) -> String {
+    OffsetDateTime::from(time).date().to_string()
+}
+
+fn pages(count: usize) -> String {
+    match count {
+        1 => "1 page".to_string(),
+        n => format!("{n} pages"),
+    }
+}
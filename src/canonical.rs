@@ -0,0 +1,416 @@
+//! Maps request paths onto the files they are stored at.
+//!
+//! A path is split into segments that are percent-decoded once, the way `ServeDir` decodes them
+//! when it looks a file up. Empty and `.` segments are dropped, and `..` drops the segment
+//! before it, as browsers do. This way every spelling of a URL lands on the same file.
+//!
+//! Anything that could reach outside the data directory, or be read differently on another
+//! platform, is refused. That covers `..` above the root, encoded slashes and backslashes, NUL
+//! and other control characters, and segments the filesystem would take for a root or a drive.
+use axum::http::StatusCode;
+use mime_guess::{Mime, mime};
+use percent_encoding::percent_decode_str;
+use std::path::{Component, Path, PathBuf};
+
+/// Maps a request path, e.g. `/Example.com/blog/`, onto the file it is stored at, relative to
+/// the data directory, e.g. `example.com/blog/index.html`.
+pub fn storage_path(path: &str, max_length: usize) -> Result<(PathBuf, Mime), StatusCode> {
+    let mut segments: Vec<String> = Vec::new();
+
+    // Browsers read `\` as `/` in http URLs, so other clients must end up at the same file
+    for raw in path.split(['/', '\\']) {
+        let segment = percent_decode_str(raw)
+            .decode_utf8()
+            .map_err(|_| StatusCode::BAD_REQUEST)?;
+
+        match &*segment {
+            "" | "." => {}
+            ".." => {
+                segments.pop().ok_or(StatusCode::BAD_REQUEST)?;
+            }
+            segment if is_plain(segment) => segments.push(segment.to_string()),
+            _ => return Err(StatusCode::BAD_REQUEST),
+        }
+    }
+
+    let Some(domain) = segments.first_mut() else {
+        return Err(StatusCode::NOT_FOUND);
+    };
+
+    // Host names are case-insensitive and may end in the root's dot
+    *domain = domain.trim_end_matches('.').to_lowercase();
+
+    // Reserved for the server's own endpoints, e.g. /_admin
+    if domain.is_empty() || domain.starts_with('_') {
+        return Err(StatusCode::NOT_FOUND);
+    }
+
+    // Hidden files include the temporary files of generations in progress
+    if segments.iter().any(|segment| segment.starts_with('.')) {
+        return Err(StatusCode::NOT_FOUND);
+    }
+
+    let url: PathBuf = segments.iter().collect();
+
+    let extension = url.extension().and_then(|x| x.to_str());
+
+    if let Some("map") = extension {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    let (url, extension) = match (segments.len(), extension) {
+        (1, _) | (_, None) => (url.join("index.html"), "html"),
+        (_, Some(ext)) => (url.clone(), ext),
+    };
+
+    if url.as_os_str().len() > max_length {
+        return Err(StatusCode::URI_TOO_LONG);
+    }
+
+    // Must default to HTML because .com is technically an extension
+    let mime_type = mime_guess::from_ext(extension).first_or(mime::TEXT_HTML);
+
+    Ok((url, mime_type))
+}
+
+/// Whether `path` reaches into a hidden file or directory, like the temporary file of a
+/// generation in progress. Decoded the way `ServeDir` decodes it, so `%2e` counts as a dot.
+pub fn is_hidden(path: &str) -> bool {
+    path.split(['/', '\\']).any(|raw| {
+        let segment = percent_decode_str(raw).decode_utf8_lossy();
+        segment.starts_with('.') && segment != "." && segment != ".."
+    })
+}
+
+/// A single file name, with nothing the filesystem would read as a separator, root or drive.
+fn is_plain(segment: &str) -> bool {
+    !segment.contains(['/', '\\'])
+        && !segment.contains(char::is_control)
+        && matches!(
+            Path::new(segment).components().collect::<Vec<_>>()[..],
+            [Component::Normal(_)]
+        )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAX: usize = 72;
+
+    fn resolve(path: &str) -> Result<PathBuf, StatusCode> {
+        storage_path(path, MAX).map(|(url, _)| url)
+    }
+
+    fn mime_of(path: &str) -> String {
+        storage_path(path, MAX).unwrap().1.essence_str().to_string()
+    }
+
+    #[test]
+    fn plain_paths_are_kept() {
+        assert_eq!(
+            resolve("/example.com/about.html"),
+            Ok("example.com/about.html".into())
+        );
+        assert_eq!(
+            resolve("/example.com/blog/2050/post.html"),
+            Ok("example.com/blog/2050/post.html".into())
+        );
+    }
+
+    #[test]
+    fn directories_map_to_their_index() {
+        for path in ["/example.com", "/example.com/", "example.com"] {
+            assert_eq!(resolve(path), Ok("example.com/index.html".into()), "{path}");
+        }
+        for path in ["/example.com/blog", "/example.com/blog/"] {
+            assert_eq!(
+                resolve(path),
+                Ok("example.com/blog/index.html".into()),
+                "{path}"
+            );
+        }
+    }
+
+    #[test]
+    fn mime_follows_the_extension() {
+        assert_eq!(mime_of("/example.com"), "text/html");
+        assert_eq!(mime_of("/example.com/blog"), "text/html");
+        assert_eq!(mime_of("/example.com/style.css"), "text/css");
+        assert_eq!(mime_of("/example.com/data.json"), "application/json");
+        assert_eq!(mime_of("/example.com/logo.svg"), "image/svg+xml");
+        assert_eq!(mime_of("/example.com/file.unknownext"), "text/html");
+    }
+
+    #[test]
+    fn equivalent_urls_share_a_file() {
+        let spellings = [
+            "/example.com/a/b.html",
+            "example.com/a/b.html",
+            "/Example.COM/a/b.html",
+            "/example.com./a/b.html",
+            "//example.com//a///b.html",
+            "/example.com/./a/./b.html",
+            "/example.com/x/../a/b.html",
+            "/example.com/a/b.html/../b.html",
+            "/example.com\\a\\b.html",
+            "/example.com/a\\b.html",
+            "/example.com/%61/b.html",
+            "/%65xample.com/a/b.html",
+            "/example.com/a/%2e/b.html",
+            "/example.com/x/%2e%2e/a/b.html",
+            "/example.com/x/.%2E/a/b.html",
+        ];
+
+        for path in spellings {
+            assert_eq!(resolve(path), Ok("example.com/a/b.html".into()), "{path}");
+        }
+    }
+
+    #[test]
+    fn paths_are_decoded_once() {
+        assert_eq!(
+            resolve("/example.com/hello%20world.html"),
+            Ok("example.com/hello world.html".into())
+        );
+        assert_eq!(
+            resolve("/example.com/%C3%A9t%C3%A9.html"),
+            Ok("example.com/été.html".into())
+        );
+        assert_eq!(
+            resolve("/example.com/100%2525.html"),
+            Ok("example.com/100%25.html".into())
+        );
+        // Not an escape, so taken literally like `ServeDir` does
+        assert_eq!(
+            resolve("/example.com/100%.html"),
+            Ok("example.com/100%.html".into())
+        );
+    }
+
+    #[test]
+    fn invalid_utf8_is_rejected() {
+        assert_eq!(
+            resolve("/example.com/%FF.html"),
+            Err(StatusCode::BAD_REQUEST)
+        );
+        assert_eq!(
+            resolve("/example.com/%C3.html"),
+            Err(StatusCode::BAD_REQUEST)
+        );
+    }
+
+    #[test]
+    fn traversal_above_the_root_is_rejected() {
+        let paths = [
+            "/..",
+            "/../etc/passwd",
+            "/example.com/../../etc/passwd",
+            "/example.com/a/../../../etc/passwd",
+            "/%2e%2e/etc/passwd",
+            "/%2E%2E/%2E%2E/etc/passwd",
+            "/example.com/%2e%2e/%2e%2e/etc/passwd",
+            "/example.com\\..\\..\\etc\\passwd",
+            "\\..\\windows\\win.ini",
+        ];
+
+        for path in paths {
+            assert_eq!(resolve(path), Err(StatusCode::BAD_REQUEST), "{path}");
+        }
+    }
+
+    #[test]
+    fn traversal_within_the_root_is_resolved() {
+        assert_eq!(
+            resolve("/example.com/../other.com/page.html"),
+            Ok("other.com/page.html".into())
+        );
+        assert_eq!(
+            resolve("/example.com/a/b/../../c.html"),
+            Ok("example.com/c.html".into())
+        );
+    }
+
+    #[test]
+    fn encoded_separators_are_rejected() {
+        let paths = [
+            "/example.com/a%2fb.html",
+            "/example.com/a%2Fb.html",
+            "/example.com/..%2f..%2fetc/passwd",
+            "/example.com/%2fetc%2fpasswd",
+            "/%2fetc/passwd",
+            "/example.com/a%5cb.html",
+            "/example.com/..%5c..%5cwin.ini",
+            "/example.com/%5C%5Cserver%5Cshare",
+        ];
+
+        for path in paths {
+            assert_eq!(resolve(path), Err(StatusCode::BAD_REQUEST), "{path}");
+        }
+    }
+
+    #[test]
+    fn control_characters_are_rejected() {
+        let paths = [
+            "/example.com/a%00.html",
+            "/example.com/a\0.html",
+            "/example.com/a.html%00.css",
+            "/%00/index.html",
+            "/example.com/a%0a.html",
+            "/example.com/a%0D%0Ab.html",
+            "/example.com/a%09.html",
+            "/example.com/a%7f.html",
+        ];
+
+        for path in paths {
+            assert_eq!(resolve(path), Err(StatusCode::BAD_REQUEST), "{path}");
+        }
+    }
+
+    #[test]
+    fn absolute_components_stay_relative() {
+        // Extra slashes collapse instead of starting over at the filesystem root
+        assert_eq!(resolve("//etc/hosts.txt"), Ok("etc/hosts.txt".into()));
+        assert_eq!(
+            resolve("/example.com//etc/hosts.txt"),
+            Ok("example.com/etc/hosts.txt".into())
+        );
+        assert_eq!(
+            resolve("/example.com///etc/passwd"),
+            Ok("example.com/etc/passwd/index.html".into())
+        );
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn drives_are_rejected() {
+        for path in [
+            "/C:/windows/win.ini",
+            "/example.com/C:",
+            "/example.com/%43:",
+        ] {
+            assert_eq!(resolve(path), Err(StatusCode::BAD_REQUEST), "{path}");
+        }
+    }
+
+    #[test]
+    fn reserved_and_hidden_paths_are_not_found() {
+        let paths = [
+            "",
+            "/",
+            "/.",
+            "//",
+            "/example.com/..",
+            "/_admin",
+            "/_meta/example.com",
+            "/./_admin",
+            "/%5fadmin",
+            "/example.com/../_admin",
+            "/.well-known/security.txt",
+            "/example.com/.git/config",
+            "/example.com/%2egit/config",
+            "/example.com/.page.html.1.tmp",
+            "/example.com/...",
+        ];
+
+        for path in paths {
+            assert_eq!(resolve(path), Err(StatusCode::NOT_FOUND), "{path}");
+        }
+    }
+
+    #[test]
+    fn hidden_paths_are_detected() {
+        let hidden = [
+            "/example.com/.index.html.1234-0.tmp",
+            "/example.com/blog/.post.html.1234-7.tmp",
+            "/example.com/%2eindex.html.1234-0.tmp",
+            "/example.com/%2Egit/config",
+            "/google.com/.query/search.html/8c3f2a91d07be465.html",
+            "/example.com/.form/login.html/0123456789abcdef.html",
+            "/example.com/%2eform/login.html/0123456789abcdef.html",
+            "/example.com\\.env",
+            "/.well-known/security.txt",
+        ];
+        for path in hidden {
+            assert!(is_hidden(path), "{path}");
+        }
+
+        let visible = [
+            "/",
+            "/example.com/index.html",
+            "/example.com/./index.html",
+            "/example.com/blog/../index.html",
+            "/example.com/file.with.dots.html",
+            "/example.com/%20.html",
+        ];
+        for path in visible {
+            assert!(!is_hidden(path), "{path}");
+        }
+    }
+
+    #[test]
+    fn source_maps_are_rejected() {
+        assert_eq!(
+            resolve("/example.com/app.js.map"),
+            Err(StatusCode::BAD_REQUEST)
+        );
+    }
+
+    #[test]
+    fn long_paths_are_rejected() {
+        let name = "a".repeat(MAX);
+        assert_eq!(
+            resolve(&format!("/example.com/{name}.html")),
+            Err(StatusCode::URI_TOO_LONG)
+        );
+
+        // Measured after decoding, and with the index appended
+        let fits = "b".repeat(MAX - "example.com/".len() - "/index.html".len());
+        assert_eq!(
+            resolve(&format!("/example.com/{fits}")),
+            Ok(format!("example.com/{fits}/index.html").into())
+        );
+        assert_eq!(
+            resolve(&format!("/example.com/{fits}b")),
+            Err(StatusCode::URI_TOO_LONG)
+        );
+    }
+
+    #[test]
+    fn every_file_lands_inside_the_data_directory() {
+        let hostile = [
+            "/../../../../etc/passwd",
+            "/example.com/../../../../etc/passwd",
+            "/example.com/%2e%2e%2f%2e%2e%2fetc%2fpasswd",
+            "/example.com/..%252f..%252fetc/passwd",
+            "/example.com/%252e%252e/%252e%252e/etc/passwd",
+            "/example.com/....//....//etc/passwd",
+            "/example.com/..;/..;/etc/passwd",
+            "/example.com/%c0%ae%c0%ae/etc/passwd",
+            "/example.com/\\\\server\\share\\file",
+            "//server/share/file",
+            "/example.com/a/./../b/../../../c",
+            "/%2e/%2e%2e/x",
+            "/example.com/%00../../etc/passwd",
+            "/example.com/x/../../y.com/../../z",
+        ];
+
+        let root = Path::new("/srv/internet");
+
+        for path in hostile {
+            let Ok(url) = resolve(path) else { continue };
+
+            assert!(
+                url.components()
+                    .all(|component| matches!(component, Component::Normal(_))),
+                "{path} resolved to {}",
+                url.display()
+            );
+            assert!(
+                root.join(&url).starts_with(root),
+                "{path} resolved to {}",
+                url.display()
+            );
+        }
+    }
+}
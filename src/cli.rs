@@ -0,0 +1,394 @@
+//! Command line interface. Without a subcommand the server starts, as it always has.
+//!
+//! The headless commands go through the same generation path as the server, so pages they
+//! produce get history, metadata and search like any other.
+use axum::http::StatusCode;
+use clap::{Args, Parser, Subcommand};
+use futures_util::StreamExt;
+use jwalk::WalkDir;
+use std::collections::{HashSet, VecDeque};
+use std::error::Error;
+use std::io::Write;
+use std::path::{Component, Path, PathBuf};
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::task::JoinSet;
+
+use crate::store::Store;
+use crate::vhost::{self, VirtualHosts};
+use crate::{AppState, generate_path};
+
+/// Temporary files younger than this may belong to a running server.
+const STALE_TEMP_AGE: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Parser)]
+#[command(version, about = "The AI-generated web")]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    #[command(flatten)]
+    pub options: Options,
+}
+
+#[derive(Args)]
+pub struct Options {
+    /// Config file [default: web2050.toml, if it exists]
+    #[arg(long, global = true)]
+    pub config: Option<PathBuf>,
+
+    /// Directory generated sites are stored in and served from, overriding DATA_DIR
+    #[arg(long, global = true)]
+    pub data_dir: Option<PathBuf>,
+
+    /// Directory for page history and quarantined output, overriding STATE_DIR
+    #[arg(long, global = true)]
+    pub state_dir: Option<PathBuf>,
+
+    /// LLM backend, overriding BACKEND: openai, ollama, anthropic or mock
+    #[arg(long, global = true)]
+    pub backend: Option<String>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Serve the generated web, generating pages on demand (the default)
+    Serve(ServeArgs),
+    /// Generate a single page and print it
+    Generate(GenerateArgs),
+    /// Pre-generate a site by following its links
+    Crawl(CrawlArgs),
+    /// Clean up old versions, quarantined output, leftover temporary files or whole domains.
+    /// Run it while the server is stopped.
+    Prune(PruneArgs),
+    /// Write the generated files to a tar archive
+    Export(ExportArgs),
+}
+
+#[derive(Args, Default)]
+pub struct ServeArgs {
+    /// Address to listen on, overriding HOST
+    #[arg(long)]
+    pub host: Option<String>,
+}
+
+#[derive(Args)]
+pub struct GenerateArgs {
+    /// Page to generate, e.g. example.com/about
+    url: String,
+
+    /// Generate a new version even if the page exists
+    #[arg(long)]
+    force: bool,
+
+    /// Don't print the page
+    #[arg(long, short)]
+    quiet: bool,
+}
+
+#[derive(Args)]
+pub struct CrawlArgs {
+    /// Page to start at, e.g. example.com
+    url: String,
+
+    /// Stop after this many pages
+    #[arg(long, default_value_t = 50)]
+    max_pages: usize,
+
+    /// Links to follow away from the start page
+    #[arg(long, default_value_t = 3)]
+    depth: usize,
+
+    /// Pages generated at once, further limited by DOMAIN_CONCURRENCY
+    #[arg(long, default_value_t = 4, value_parser = clap::value_parser!(u16).range(1..))]
+    jobs: u16,
+}
+
+#[derive(Args)]
+pub struct PruneArgs {
+    /// Keep only the newest N versions of every page, plus the live one
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+    keep_versions: Option<u32>,
+
+    /// Delete all quarantined partial output
+    #[arg(long)]
+    quarantine: bool,
+
+    /// Delete a domain entirely, with its history. Can be repeated
+    #[arg(long)]
+    domain: Vec<String>,
+
+    /// Only list what would be removed
+    #[arg(long)]
+    dry_run: bool,
+}
+
+#[derive(Args)]
+pub struct ExportArgs {
+    /// Archive to write, `-` for stdout
+    output: PathBuf,
+
+    /// Only export these domains, along with the shared root files. Can be repeated
+    #[arg(long)]
+    domain: Vec<String>,
+}
+
+pub async fn generate(state: AppState, args: GenerateArgs) -> Result<(), Box<dyn Error>> {
+    let path = page_path(&args.url);
+
+    let response = generate_path(state, &path, None, None, None, args.force)
+        .await
+        .map_err(|status| format!("cannot generate {path}: {status}"))?;
+
+    let mut body = response.into_body().into_data_stream();
+    let mut stdout = tokio::io::stdout();
+
+    while let Some(chunk) = body.next().await {
+        let chunk = chunk.map_err(|e| format!("generating {path} failed: {e}"))?;
+
+        if !args.quiet {
+            stdout.write_all(&chunk).await?;
+        }
+    }
+
+    stdout.flush().await?;
+    Ok(())
+}
+
+pub async fn crawl(state: AppState, args: CrawlArgs) -> Result<(), Box<dyn Error>> {
+    let start = page_path(&args.url);
+    let (resolved, _) = state
+        .store
+        .resolve(&start)
+        .map_err(|status| format!("cannot crawl {start}: {status}"))?;
+    let domain = resolved
+        .iter()
+        .next()
+        .ok_or("nothing to crawl")?
+        .to_string_lossy()
+        .into_owned();
+
+    let mut seen = HashSet::from([resolved]);
+    let mut queue = VecDeque::from([(start, 0, None)]);
+    let mut tasks = JoinSet::new();
+    let (mut done, mut failed) = (0, 0);
+
+    loop {
+        while tasks.len() < args.jobs as usize
+            && let Some((path, depth, referer)) = queue.pop_front()
+        {
+            let state = state.clone();
+            tasks.spawn(async move {
+                let page = fetch(state, &path, referer.as_deref()).await;
+                (path, depth, page)
+            });
+        }
+
+        let Some(result) = tasks.join_next().await else {
+            break;
+        };
+        let (path, depth, page) = result?;
+
+        let content = match page {
+            Ok(content) => content,
+            Err(e) => {
+                failed += 1;
+                eprintln!("{path}: {e}");
+                continue;
+            }
+        };
+
+        done += 1;
+        eprintln!("[{done}/{}] {path}", seen.len());
+
+        if depth >= args.depth {
+            continue;
+        }
+
+        for link in links(&content, &domain, state.vhosts.as_deref()) {
+            if seen.len() >= args.max_pages {
+                break;
+            }
+
+            if let Ok((resolved, _)) = state.store.resolve(&link)
+                && seen.insert(resolved)
+            {
+                queue.push_back((link, depth + 1, Some(path.clone())));
+            }
+        }
+    }
+
+    eprintln!("crawled {done} pages of {domain}, {failed} failed");
+    Ok(())
+}
+
+/// Generates `path`, linked from `referer`, unless it exists. Returns its content.
+async fn fetch(state: AppState, path: &str, referer: Option<&str>) -> Result<String, String> {
+    let response = generate_path(state, path, None, referer, None, false)
+        .await
+        .map_err(|status: StatusCode| status.to_string())?;
+
+    let mut body = response.into_body().into_data_stream();
+    let mut content = Vec::new();
+
+    while let Some(chunk) = body.next().await {
+        content.extend_from_slice(&chunk.map_err(|e| e.to_string())?);
+    }
+
+    Ok(String::from_utf8_lossy(&content).into_owned())
+}
+
+/// Same-site links of a page, i.e. quoted `href` and `src` attributes below `/<domain>`. With
+/// virtual hosts, links are root-relative or point at the site's host instead.
+fn links(content: &str, domain: &str, vhosts: Option<&VirtualHosts>) -> Vec<String> {
+    let site = format!("/{domain}");
+    let mut links = Vec::new();
+
+    for attribute in ["href=", "src="] {
+        for (i, _) in content.match_indices(attribute) {
+            let rest = &content[i + attribute.len()..];
+
+            let Some(quote) = rest.chars().next().filter(|c| matches!(c, '"' | '\'')) else {
+                continue;
+            };
+            let Some(end) = rest[1..].find(quote) else {
+                continue;
+            };
+
+            let link = rest[1..1 + end]
+                .split(['#', '?'])
+                .next()
+                .unwrap_or_default();
+
+            let link = match vhosts {
+                Some(vhosts) if link.contains("://") => match link.parse() {
+                    Ok(uri) => vhosts.path(&uri),
+                    Err(_) => continue,
+                },
+                Some(_)
+                    if link.starts_with('/') && !link.starts_with("//") && !vhost::shared(link) =>
+                {
+                    format!("{site}{link}")
+                }
+                _ => link.to_string(),
+            };
+
+            if link == site || link.starts_with(&format!("{site}/")) {
+                links.push(link);
+            }
+        }
+    }
+
+    links
+}
+
+pub async fn prune(store: &Store, args: PruneArgs) -> Result<(), Box<dyn Error>> {
+    let dry_run = args.dry_run;
+    let domains = domains(&args.domain)?;
+
+    let mut removed = store
+        .remove_stale_temp_files(STALE_TEMP_AGE, dry_run)
+        .await?;
+
+    if args.quarantine {
+        removed.extend(store.clear_quarantine(dry_run).await?);
+    }
+
+    if let Some(keep) = args.keep_versions {
+        removed.extend(store.trim_history(keep as usize, dry_run).await?);
+    }
+
+    for domain in &domains {
+        removed.extend(store.remove(domain, dry_run).await?);
+    }
+
+    for path in &removed {
+        println!("{}", path.display());
+    }
+
+    eprintln!(
+        "{} {} paths",
+        if dry_run { "would remove" } else { "removed" },
+        removed.len()
+    );
+
+    Ok(())
+}
+
+/// The directories of domains given on the command line, spelled the way
+/// `canonical::storage_path` stores them, so `Example.com.` is `example.com`.
+fn domains(domains: &[String]) -> Result<Vec<String>, Box<dyn Error>> {
+    domains
+        .iter()
+        .map(|domain| {
+            let normalized = domain.trim_end_matches('.').to_lowercase();
+            let mut components = Path::new(&normalized).components();
+            match (components.next(), components.next()) {
+                (Some(Component::Normal(_)), None) => Ok(normalized),
+                _ => Err(format!("`{domain}` is not a domain").into()),
+            }
+        })
+        .collect()
+}
+
+/// Blocking.
+pub fn export(root: &Path, args: ExportArgs) -> Result<(), Box<dyn Error>> {
+    let output: Box<dyn Write> = match args.output.to_str() {
+        Some("-") => Box::new(std::io::stdout().lock()),
+        _ => Box::new(std::fs::File::create(&args.output)?),
+    };
+
+    let domains = domains(&args.domain)?;
+    let mut archive = tar::Builder::new(std::io::BufWriter::new(output));
+    let mut count = 0;
+
+    // Hidden files are in-progress generations
+    for entry in WalkDir::new(root).skip_hidden(true) {
+        let entry = entry?;
+        if !entry.file_type().is_file() {
+            continue;
+        }
+
+        let path = entry.path();
+        let rel = path.strip_prefix(root)?;
+
+        // Files directly in the root are shared by every domain
+        let shared = rel.components().count() == 1;
+        if !shared && !domains.is_empty() && !domains.iter().any(|domain| rel.starts_with(domain)) {
+            continue;
+        }
+
+        archive.append_path_with_name(&path, rel)?;
+        count += 1;
+    }
+
+    archive.into_inner()?.flush()?;
+    eprintln!("exported {count} files");
+
+    Ok(())
+}
+
+/// Accepts `example.com/about`, `/example.com/about` or `https://example.com/about`.
+fn page_path(url: &str) -> String {
+    let url = url
+        .strip_prefix("https://")
+        .or_else(|| url.strip_prefix("http://"))
+        .unwrap_or(url);
+
+    format!("/{}", url.trim_start_matches('/'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn domains_are_normalized_like_stored_paths() {
+        let args = ["Example.COM".to_string(), "example.org.".to_string()];
+        assert_eq!(domains(&args).unwrap(), ["example.com", "example.org"]);
+
+        for domain in ["", ".", "..", "a/b", "/etc"] {
+            assert!(domains(&[domain.to_string()]).is_err(), "{domain}");
+        }
+    }
+}
@@ -0,0 +1,281 @@
+//! Server configuration.
+//!
+//! Settings are read from `web2050.toml` (or `--config`), then overridden by environment
+//! variables, including a `.env` file, and finally by command line flags. Everything is
+//! validated once at startup so a bad value stops the server with a message instead of a panic
+//! later on.
+use axum::http::HeaderValue;
+use dotenvy::EnvMap;
+use serde::{Deserialize, Deserializer};
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use crate::backend::BackendConfig;
+use crate::generation::DisconnectPolicy;
+use crate::profile::Profile;
+use crate::store::PartialOutput;
+
+/// Read when no `--config` is given, if it exists.
+pub const DEFAULT_PATH: &str = "web2050.toml";
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// `HOST`, the address to listen on.
+    pub host: Option<String>,
+    /// `DATA_DIR`, where generated sites are stored and served from.
+    pub data_dir: PathBuf,
+    /// `STATE_DIR`, where history and quarantine are kept.
+    pub state_dir: PathBuf,
+    /// `PROMPT_DIR`, where `system.jinja` and `user.jinja` are read from.
+    pub prompt_dir: PathBuf,
+    /// `ADMIN_TOKEN`, enables `/_admin` when set.
+    pub admin_token: Option<String>,
+    /// `MAX_PATH_LENGTH`, in bytes, of the stored file a request maps to.
+    pub max_path_length: usize,
+    /// `CONTENT_SECURITY_POLICY`, sent with every response.
+    pub content_security_policy: String,
+    /// `PARTIAL_OUTPUT`
+    #[serde(deserialize_with = "parse")]
+    pub partial_output: PartialOutput,
+    /// `DOMAIN_CONCURRENCY`, files of one domain generated at once.
+    pub domain_concurrency: usize,
+    /// `ON_DISCONNECT`
+    #[serde(deserialize_with = "parse")]
+    pub on_disconnect: DisconnectPolicy,
+    /// `EVENT_BUFFER`, events kept for slow `/_events` clients before they skip ahead.
+    pub event_buffer: usize,
+    /// `CONTEXT_BUDGET`, estimated tokens of the domain's files given to the model.
+    pub context_budget: usize,
+    /// `MAX_ASSET_SIZE`, in bytes. Larger files of a domain are listed but never read.
+    pub max_asset_size: u64,
+    /// `MAX_TOTAL_ASSET_SIZE`, in bytes, read from a domain for one generation.
+    pub max_total_asset_size: u64,
+    /// `QUERY_PARAMS`, comma separated in the environment. Query parameters that get a page of
+    /// their own, none by default.
+    pub query_params: Vec<String>,
+    /// `MAX_QUERY_LENGTH`, in bytes, of the selected query parameters.
+    pub max_query_length: usize,
+    /// `MAX_FORM_SIZE`, in bytes, of the fields of a submitted form.
+    pub max_form_size: usize,
+    /// `CACHE_FORM_RESPONSES`, keeps the pages generated for submitted forms and serves them
+    /// again for the same fields.
+    pub cache_form_responses: bool,
+    /// `MAX_VARIANTS`, pages kept for different query parameters or forms of one path.
+    pub max_variants: usize,
+    /// `VHOST_SUFFIX`, serves `example.com.<suffix>` from the `example.com` directory and has
+    /// the model link pages root-relative.
+    pub vhost_suffix: Option<String>,
+    pub backend: BackendConfig,
+    /// `[profiles.<name>]`, only settable in the file.
+    pub profiles: BTreeMap<String, Profile>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            host: None,
+            data_dir: "internet".into(),
+            state_dir: ".web2050".into(),
+            prompt_dir: "prompts".into(),
+            admin_token: None,
+            max_path_length: 72,
+            content_security_policy: "default-src 'self'; script-src 'self' 'unsafe-inline'; style-src 'self' 'unsafe-inline'; img-src *; font-src 'self'; object-src 'none'; base-uri 'self'; frame-ancestors 'none';".to_string(),
+            partial_output: PartialOutput::Discard,
+            domain_concurrency: 4,
+            on_disconnect: DisconnectPolicy::Finish,
+            event_buffer: 256,
+            context_budget: 16000,
+            max_asset_size: 256 * 1024,
+            max_total_asset_size: 8 * 1024 * 1024,
+            query_params: Vec::new(),
+            max_query_length: 256,
+            max_form_size: 16 * 1024,
+            cache_form_responses: false,
+            max_variants: 1000,
+            vhost_suffix: None,
+            backend: BackendConfig::default(),
+            profiles: BTreeMap::new(),
+        }
+    }
+}
+
+impl Config {
+    /// Reads `path`, or [`DEFAULT_PATH`] if it exists, and applies the environment on top.
+    pub fn load(path: Option<&Path>, env: &EnvMap) -> Result<Self, String> {
+        let (path, required) = match path {
+            Some(path) => (path, true),
+            None => (Path::new(DEFAULT_PATH), false),
+        };
+
+        let mut config = match std::fs::read_to_string(path) {
+            Ok(toml) => toml::from_str(&toml)
+                .map_err(|e| format!("invalid config file {}: {e}", path.display()))?,
+            Err(e) if !required && e.kind() == std::io::ErrorKind::NotFound => Self::default(),
+            Err(e) => return Err(format!("cannot read config file {}: {e}", path.display())),
+        };
+
+        config.apply_env(env)?;
+        Ok(config)
+    }
+
+    fn apply_env(&mut self, env: &EnvMap) -> Result<(), String> {
+        set_option(env, "HOST", &mut self.host)?;
+        set(env, "DATA_DIR", &mut self.data_dir)?;
+        set(env, "STATE_DIR", &mut self.state_dir)?;
+        set(env, "PROMPT_DIR", &mut self.prompt_dir)?;
+        set_option(env, "ADMIN_TOKEN", &mut self.admin_token)?;
+        set(env, "MAX_PATH_LENGTH", &mut self.max_path_length)?;
+        set(
+            env,
+            "CONTENT_SECURITY_POLICY",
+            &mut self.content_security_policy,
+        )?;
+        set(env, "PARTIAL_OUTPUT", &mut self.partial_output)?;
+        set(env, "DOMAIN_CONCURRENCY", &mut self.domain_concurrency)?;
+        set(env, "ON_DISCONNECT", &mut self.on_disconnect)?;
+        set(env, "EVENT_BUFFER", &mut self.event_buffer)?;
+        set(env, "CONTEXT_BUDGET", &mut self.context_budget)?;
+        set(env, "MAX_ASSET_SIZE", &mut self.max_asset_size)?;
+        set(env, "MAX_TOTAL_ASSET_SIZE", &mut self.max_total_asset_size)?;
+        set(env, "MAX_QUERY_LENGTH", &mut self.max_query_length)?;
+        set(env, "MAX_FORM_SIZE", &mut self.max_form_size)?;
+        set(env, "CACHE_FORM_RESPONSES", &mut self.cache_form_responses)?;
+        set(env, "MAX_VARIANTS", &mut self.max_variants)?;
+        set_option(env, "VHOST_SUFFIX", &mut self.vhost_suffix)?;
+
+        if let Ok(params) = env.var("QUERY_PARAMS") {
+            self.query_params = params
+                .split(',')
+                .map(str::trim)
+                .filter(|param| !param.is_empty())
+                .map(str::to_string)
+                .collect();
+        }
+
+        let backend = &mut self.backend;
+        set(env, "BACKEND", &mut backend.kind)?;
+        set_option(env, "BACKEND_URL", &mut backend.base_url)?;
+        set_option(env, "MODEL", &mut backend.model)?;
+        set_option(env, "API_KEY", &mut backend.api_key)?;
+        set_option(env, "MAX_TOKENS", &mut backend.max_tokens)?;
+
+        let mock = &mut backend.mock;
+        set_option(env, "MOCK_RESPONSE", &mut mock.response)?;
+        set(env, "MOCK_CHUNK_SIZE", &mut mock.chunk_size)?;
+        set(env, "MOCK_LATENCY_MS", &mut mock.latency_ms)?;
+        set_option(env, "MOCK_FAIL_AFTER", &mut mock.fail_after)?;
+
+        if let Ok(failure) = env.var("MOCK_FAIL") {
+            mock.failure = match failure.as_str() {
+                "none" => None,
+                other => Some(other.parse().map_err(|e| invalid("MOCK_FAIL", other, e))?),
+            };
+        }
+
+        Ok(())
+    }
+
+    /// Checks everything that can be checked before serving.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.max_path_length == 0 {
+            return Err("max_path_length (MAX_PATH_LENGTH) must be at least 1".into());
+        }
+
+        if self.domain_concurrency == 0 {
+            return Err("domain_concurrency (DOMAIN_CONCURRENCY) must be at least 1".into());
+        }
+
+        if self.event_buffer == 0 {
+            return Err("event_buffer (EVENT_BUFFER) must be at least 1".into());
+        }
+
+        if self.query_params.iter().any(String::is_empty) {
+            return Err("query_params (QUERY_PARAMS) must not contain empty names".into());
+        }
+
+        if self.max_query_length == 0 {
+            return Err("max_query_length (MAX_QUERY_LENGTH) must be at least 1".into());
+        }
+
+        if self.max_form_size == 0 {
+            return Err("max_form_size (MAX_FORM_SIZE) must be at least 1".into());
+        }
+
+        if self.max_variants == 0 {
+            return Err("max_variants (MAX_VARIANTS) must be at least 1".into());
+        }
+
+        if let Some(suffix) = &self.vhost_suffix {
+            let suffix = suffix.trim_start_matches('.');
+            if suffix.is_empty()
+                || !suffix
+                    .bytes()
+                    .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'.')
+            {
+                return Err(format!(
+                    "vhost_suffix (VHOST_SUFFIX) `{suffix}` must be a host name, e.g. `web2050.local`"
+                ));
+            }
+        }
+
+        if self.backend.mock.chunk_size == 0 {
+            return Err("backend.mock.chunk_size (MOCK_CHUNK_SIZE) must be at least 1".into());
+        }
+
+        for (name, profile) in &self.profiles {
+            profile.validate(name)?;
+        }
+
+        self.csp()?;
+        Ok(())
+    }
+
+    /// The Content-Security-Policy header.
+    pub fn csp(&self) -> Result<HeaderValue, String> {
+        HeaderValue::from_str(&self.content_security_policy).map_err(|_| {
+            "content_security_policy (CONTENT_SECURITY_POLICY) must be a valid header value"
+                .to_string()
+        })
+    }
+}
+
+/// Deserializes through `FromStr`, so config files and the environment accept the same values.
+pub fn parse<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr,
+    T::Err: Display,
+{
+    String::deserialize(deserializer)?
+        .parse()
+        .map_err(serde::de::Error::custom)
+}
+
+fn invalid(key: &str, value: &str, error: impl Display) -> String {
+    format!("invalid {key} `{value}`: {error}")
+}
+
+fn set<T>(env: &EnvMap, key: &str, target: &mut T) -> Result<(), String>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    if let Ok(value) = env.var(key) {
+        *target = value.parse().map_err(|e| invalid(key, &value, e))?;
+    }
+    Ok(())
+}
+
+fn set_option<T>(env: &EnvMap, key: &str, target: &mut Option<T>) -> Result<(), String>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    if let Ok(value) = env.var(key) {
+        *target = Some(value.parse().map_err(|e| invalid(key, &value, e))?);
+    }
+    Ok(())
+}
@@ -0,0 +1,509 @@
+//! Chooses what the model gets to see of the domain it is generating a file for.
+//!
+//! Files are ranked by how useful they are for the new one: the page that linked to it, shared
+//! stylesheets, the home page that holds the layout, files many others link to and files
+//! nearby. They are included in full while the token budget allows, then cut short, and the
+//! rest only appear in a listing of the domain, summarized while there is room.
+use serde::Serialize;
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use crate::assets::AssetList;
+use crate::search;
+
+/// Most tokenizers average about four bytes of English or code per token.
+const BYTES_PER_TOKEN: usize = 4;
+
+/// Tokens spent on a file besides its content, for the path and code fences.
+const FILE_OVERHEAD: usize = 8;
+
+/// A file cut shorter than this many tokens is not worth including.
+const MIN_EXCERPT: usize = 256;
+
+/// At most this share of the budget is held back for the listing, i.e. a quarter.
+const LISTING_SHARE: usize = 4;
+
+#[derive(Serialize)]
+pub struct Context {
+    /// In full or cut short, most useful first.
+    pub files: Vec<File>,
+    /// Listed by path, with a summary where the budget allows.
+    pub others: Vec<Other>,
+    /// Files that did not even fit in the listing.
+    pub omitted: usize,
+}
+
+#[derive(Serialize)]
+pub struct File {
+    /// e.g. `/example.com/style.css`
+    pub path: String,
+    pub content: String,
+    pub truncated: bool,
+}
+
+#[derive(Serialize)]
+pub struct Other {
+    pub path: String,
+    pub summary: Option<String>,
+}
+
+struct Candidate {
+    priority: usize,
+    rel: PathBuf,
+    content: String,
+    summary: String,
+    /// Skipped by the reader, so only its description can be listed.
+    skipped: bool,
+}
+
+/// Fits the files of `target`'s domain into `budget` tokens. `target` and `referer` are
+/// relative to `root`, e.g. `example.com/about.html`. With `root_relative`, pages link within
+/// their site by root-relative paths, as they do on virtual hosts.
+pub fn build(
+    assets: AssetList,
+    root: &Path,
+    target: &Path,
+    referer: Option<&Path>,
+    budget: usize,
+    root_relative: bool,
+) -> Context {
+    let relative = |path: &Path| path.strip_prefix(root).unwrap_or(path).to_path_buf();
+
+    let files: Vec<(PathBuf, String)> = assets
+        .assets
+        .into_iter()
+        .map(|asset| (relative(&asset.path), asset.content))
+        .collect();
+
+    let inbound = inbound_links(&files, target, root_relative);
+    let links = |rel: &Path| inbound.get(rel).copied().unwrap_or_default();
+
+    let mut candidates: Vec<Candidate> = files
+        .into_iter()
+        .map(|(rel, content)| {
+            let extension = rel
+                .extension()
+                .map(|extension| extension.to_string_lossy().into_owned())
+                .unwrap_or_default();
+
+            Candidate {
+                priority: priority(&rel, target, referer, links(&rel)),
+                summary: search::describe(&extension, &content),
+                rel,
+                content,
+                skipped: false,
+            }
+        })
+        .chain(assets.skipped.into_iter().map(|skipped| {
+            let rel = relative(&skipped.path);
+
+            Candidate {
+                priority: priority(&rel, target, referer, links(&rel)),
+                summary: skipped.reason.to_string(),
+                rel,
+                content: String::new(),
+                skipped: true,
+            }
+        }))
+        .collect();
+    candidates.sort_by(|a, b| {
+        (Reverse(a.priority), a.content.len(), &a.rel).cmp(&(
+            Reverse(b.priority),
+            b.content.len(),
+            &b.rel,
+        ))
+    });
+
+    // Hold back enough to list and summarize everything, should it come to that
+    let listing: usize = candidates
+        .iter()
+        .map(|candidate| listing_cost(&candidate.rel) + tokens(&candidate.summary))
+        .sum();
+    let reserve = listing.min(budget / LISTING_SHARE);
+    let mut left = budget - reserve;
+
+    let mut context = Context {
+        files: Vec::new(),
+        others: Vec::new(),
+        omitted: 0,
+    };
+    let mut rest = Vec::new();
+
+    for candidate in candidates {
+        let cost = FILE_OVERHEAD + tokens(&candidate.content);
+
+        if candidate.skipped {
+            rest.push(candidate);
+        } else if cost <= left {
+            left -= cost;
+            context.files.push(File {
+                path: url(&candidate.rel),
+                content: candidate.content,
+                truncated: false,
+            });
+        } else if left >= FILE_OVERHEAD + MIN_EXCERPT {
+            let content = excerpt(&candidate.content, (left - FILE_OVERHEAD) * BYTES_PER_TOKEN);
+            left = left.saturating_sub(FILE_OVERHEAD + tokens(content));
+            context.files.push(File {
+                path: url(&candidate.rel),
+                content: content.to_string(),
+                truncated: true,
+            });
+        } else {
+            rest.push(candidate);
+        }
+    }
+
+    // Every path first, then summaries for as many as still fit
+    left += reserve;
+    let mut listed = Vec::new();
+
+    for candidate in rest {
+        let cost = listing_cost(&candidate.rel);
+
+        if cost <= left {
+            left -= cost;
+            listed.push(candidate);
+        } else {
+            context.omitted += 1;
+        }
+    }
+
+    for candidate in listed {
+        let cost = tokens(&candidate.summary);
+
+        let summary = (!candidate.summary.is_empty() && cost <= left).then(|| {
+            left -= cost;
+            candidate.summary
+        });
+
+        context.others.push(Other {
+            path: url(&candidate.rel),
+            summary,
+        });
+    }
+
+    context
+}
+
+fn priority(rel: &Path, target: &Path, referer: Option<&Path>, links: usize) -> usize {
+    let mut priority = 0;
+
+    // What the visitor just saw, so the new page should continue it
+    if referer == Some(rel) {
+        priority += 1000;
+    }
+
+    // Shared styles and the layout every other page repeats
+    if rel.extension().is_some_and(|extension| extension == "css") {
+        priority += 300;
+    }
+    if rel.iter().count() == 2 && rel.ends_with("index.html") {
+        priority += 300;
+    }
+
+    priority += 50 * links.min(10);
+
+    // Nearby files tend to have more in common with the new one
+    let shared = rel
+        .parent()
+        .into_iter()
+        .flat_map(Path::iter)
+        .zip(target.parent().into_iter().flat_map(Path::iter))
+        .take_while(|(a, b)| a == b)
+        .count();
+    priority += 20 * shared;
+
+    if rel.extension() == target.extension() {
+        priority += 10;
+    }
+
+    priority
+}
+
+/// How many files link to each file, counting same-domain references in any format: absolute
+/// ones like `/example.com/about.html`, or root-relative ones like `/about.html` with
+/// `root_relative`.
+fn inbound_links(
+    files: &[(PathBuf, String)],
+    target: &Path,
+    root_relative: bool,
+) -> HashMap<PathBuf, usize> {
+    let Some(domain) = target.iter().next() else {
+        return HashMap::new();
+    };
+    let domain = domain.to_string_lossy();
+    let prefix = format!("/{domain}/");
+
+    let mut inbound = HashMap::new();
+
+    for (source, content) in files {
+        let mut targets = HashSet::new();
+
+        // Where the path within the domain starts
+        let starts: Vec<usize> = match root_relative {
+            true => content
+                .match_indices('/')
+                .map(|(i, _)| i)
+                .filter(|&i| {
+                    // Opening a quoted, `url()` or unquoted attribute value, and not `//host`
+                    content[..i].ends_with(['"', '\'', '(', '='])
+                        && !content[i + 1..].starts_with('/')
+                })
+                .map(|i| i + 1)
+                .collect(),
+            false => content
+                .match_indices(&prefix)
+                .map(|(i, _)| i + prefix.len())
+                .collect(),
+        };
+
+        for start in starts {
+            let rest = &content[start..];
+            let end = rest
+                .find(['"', '\'', '(', ')', '<', '>', '?', '#', ' ', '\n', '\t'])
+                .unwrap_or(rest.len());
+
+            let mut link = PathBuf::from(domain.as_ref()).join(&rest[..end]);
+            if rest[..end].is_empty() || rest[..end].ends_with('/') {
+                link.push("index.html");
+            }
+
+            if &link != source {
+                targets.insert(link);
+            }
+        }
+
+        for link in targets {
+            *inbound.entry(link).or_default() += 1;
+        }
+    }
+
+    inbound
+}
+
+fn tokens(text: &str) -> usize {
+    text.len().div_ceil(BYTES_PER_TOKEN)
+}
+
+fn listing_cost(rel: &Path) -> usize {
+    // Plus the bullet and line break
+    tokens(&rel.to_string_lossy()) + 2
+}
+
+/// The start of `content`, at most `max` bytes, ending at a line break if there is one late
+/// enough.
+fn excerpt(content: &str, max: usize) -> &str {
+    let mut end = max.min(content.len());
+    while !content.is_char_boundary(end) {
+        end -= 1;
+    }
+
+    match content[..end].rfind('\n') {
+        Some(line) if line >= end / 2 => &content[..line],
+        _ => &content[..end],
+    }
+}
+
+fn url(rel: &Path) -> String {
+    format!("/{}", rel.to_string_lossy())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assets::{Asset, Reason, Skipped};
+
+    fn assets(files: &[(&str, &str)]) -> AssetList {
+        AssetList {
+            assets: files
+                .iter()
+                .map(|(path, content)| Asset {
+                    path: Path::new("/data").join(path),
+                    content: content.to_string(),
+                })
+                .collect(),
+            skipped: Vec::new(),
+        }
+    }
+
+    fn paths(files: &[File]) -> Vec<&str> {
+        files.iter().map(|file| file.path.as_str()).collect()
+    }
+
+    #[test]
+    fn files_are_ordered_by_priority() {
+        let assets = assets(&[
+            ("example.com/other/deep.html", "x"),
+            ("example.com/blog/post.html", "x"),
+            ("example.com/index.html", "x"),
+            ("example.com/style.css", "x"),
+            ("example.com/from.html", "x"),
+            ("example.com/notes.txt", "x"),
+        ]);
+
+        let context = build(
+            assets,
+            Path::new("/data"),
+            Path::new("example.com/blog/new.html"),
+            Some(Path::new("example.com/from.html")),
+            16000,
+            false,
+        );
+
+        assert_eq!(
+            paths(&context.files),
+            [
+                "/example.com/from.html",
+                "/example.com/index.html",
+                "/example.com/style.css",
+                "/example.com/blog/post.html",
+                "/example.com/other/deep.html",
+                "/example.com/notes.txt",
+            ]
+        );
+        assert!(context.others.is_empty());
+        assert_eq!(context.omitted, 0);
+    }
+
+    #[test]
+    fn linked_files_rank_higher() {
+        let files = [
+            (
+                "example.com/a.html",
+                r#"<a href="/example.com/linked.txt">"#,
+            ),
+            ("example.com/b.html", "<a href=/example.com/linked.txt>"),
+            ("example.com/c.txt", "x"),
+            ("example.com/linked.txt", "x"),
+        ];
+        let target = Path::new("example.com/new.html");
+
+        let context = build(
+            assets(&files),
+            Path::new("/data"),
+            target,
+            None,
+            16000,
+            false,
+        );
+        assert_eq!(context.files[0].path, "/example.com/linked.txt");
+
+        // Virtual hosts link root-relative, but never protocol-relative
+        let files = [
+            (
+                "example.com/a.html",
+                r#"<a href="/linked.txt"><a href="//c.txt">"#,
+            ),
+            ("example.com/b.css", "a { background: url(/linked.txt) }"),
+            ("example.com/c.txt", "x"),
+            ("example.com/linked.txt", "x"),
+        ];
+        let inbound = inbound_links(
+            &files.map(|(path, content)| (PathBuf::from(path), content.to_string())),
+            target,
+            true,
+        );
+        assert_eq!(inbound.get(Path::new("example.com/linked.txt")), Some(&2));
+        assert_eq!(inbound.get(Path::new("example.com/c.txt")), None);
+
+        let context = build(
+            assets(&files),
+            Path::new("/data"),
+            target,
+            None,
+            16000,
+            true,
+        );
+        assert_eq!(context.files[1].path, "/example.com/linked.txt");
+    }
+
+    #[test]
+    fn the_budget_is_respected() {
+        let big = "line of text\n".repeat(1000);
+        let mut files: Vec<(String, String)> = (0..20)
+            .map(|i| (format!("example.com/page{i:02}.html"), big.clone()))
+            .collect();
+        files.push(("example.com/tiny.html".to_string(), "x".to_string()));
+        let files: Vec<(&str, &str)> = files
+            .iter()
+            .map(|(path, content)| (path.as_str(), content.as_str()))
+            .collect();
+
+        let budget = 5000;
+        let mut assets = assets(&files);
+        assets.skipped.push(Skipped {
+            path: PathBuf::from("/data/example.com/logo.png"),
+            reason: Reason::Binary {
+                mime: "image/png".to_string(),
+                size: 10,
+            },
+        });
+
+        let context = build(
+            assets,
+            Path::new("/data"),
+            Path::new("example.com/new.html"),
+            None,
+            budget,
+            false,
+        );
+
+        let used: usize = context
+            .files
+            .iter()
+            .map(|file| FILE_OVERHEAD + tokens(&file.content))
+            .chain(context.others.iter().map(|other| {
+                listing_cost(Path::new(&other.path[1..]))
+                    + other.summary.as_deref().map_or(0, tokens)
+            }))
+            .sum();
+        assert!(used <= budget, "{used}");
+
+        // Smallest first among equals, then full files until one has to be cut short
+        assert_eq!(context.files[0].path, "/example.com/tiny.html");
+        assert!(context.files.iter().any(|file| file.truncated));
+        assert!(context.files.iter().filter(|file| file.truncated).count() <= 1);
+
+        // Everything is accounted for exactly once
+        assert_eq!(
+            context.files.len() + context.others.len() + context.omitted,
+            22
+        );
+        assert!(
+            context
+                .others
+                .iter()
+                .any(|other| other.path == "/example.com/logo.png")
+        );
+
+        // Too small a budget for anything but a few paths
+        let context = build(
+            self::assets(&files),
+            Path::new("/data"),
+            Path::new("example.com/new.html"),
+            None,
+            40,
+            false,
+        );
+        assert!(context.files.iter().all(|file| file.content.len() <= 1));
+        assert!(context.omitted > 0);
+        assert_eq!(
+            context.files.len() + context.others.len() + context.omitted,
+            21
+        );
+    }
+
+    #[test]
+    fn excerpts_end_on_char_boundaries() {
+        assert_eq!(excerpt("ééé", 3), "é");
+        assert_eq!(excerpt("ééé", 4), "éé");
+        assert_eq!(excerpt("ééé", 100), "ééé");
+        assert_eq!(excerpt("日本", 2), "");
+
+        // At a line break, unless that throws away more than half
+        assert_eq!(excerpt("one\ntwo\nthree", 10), "one\ntwo");
+        assert_eq!(excerpt("a\nlong line here", 12), "a\nlong line ");
+    }
+}
@@ -0,0 +1,116 @@
+//! Server-wide feed of generation activity, served as server-sent events at `/_events`.
+//!
+//! Every event is named after its `type` and carries a JSON body. A client connecting while
+//! files are being generated first receives a `progress` event for each of them.
+use axum::extract::State;
+use axum::response::sse::{self, KeepAlive, Sse};
+use futures_util::Stream;
+use serde::Serialize;
+use std::convert::Infallible;
+use std::path::Path;
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::AppState;
+
+/// Minimum time between two progress events of one generation.
+pub const PROGRESS_INTERVAL: Duration = Duration::from_millis(250);
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Event {
+    Started {
+        path: String,
+    },
+    Progress {
+        path: String,
+        bytes: usize,
+    },
+    Committed {
+        path: String,
+        version: u32,
+        bytes: usize,
+    },
+    Failed {
+        path: String,
+        reason: String,
+    },
+}
+
+pub struct Events {
+    tx: broadcast::Sender<Event>,
+}
+
+impl Event {
+    pub fn started(path: &Path) -> Self {
+        Self::Started {
+            path: path.to_string_lossy().into_owned(),
+        }
+    }
+
+    pub fn failed(path: &Path, reason: impl ToString) -> Self {
+        Self::Failed {
+            path: path.to_string_lossy().into_owned(),
+            reason: reason.to_string(),
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Started { .. } => "started",
+            Self::Progress { .. } => "progress",
+            Self::Committed { .. } => "committed",
+            Self::Failed { .. } => "failed",
+        }
+    }
+}
+
+impl Events {
+    /// Slow clients skip events rather than holding up generation, once `capacity` of them
+    /// are queued.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            tx: broadcast::channel(capacity).0,
+        }
+    }
+
+    pub fn send(&self, event: Event) {
+        // Nobody listening is fine
+        let _ = self.tx.send(event);
+    }
+}
+
+pub async fn stream(
+    State(state): State<AppState>,
+) -> Sse<impl Stream<Item = Result<sse::Event, Infallible>>> {
+    // Subscribe before the snapshot so nothing falls in between
+    let mut rx = state.events.tx.subscribe();
+    let snapshot = state.generations.snapshot();
+
+    let stream = async_stream::stream! {
+        for (path, bytes) in snapshot {
+            yield Ok(encode(&Event::Progress {
+                path: path.to_string_lossy().into_owned(),
+                bytes,
+            }));
+        }
+
+        loop {
+            match rx.recv().await {
+                Ok(event) => yield Ok(encode(&event)),
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => break,
+            }
+        }
+    };
+
+    Sse::new(stream).keep_alive(KeepAlive::default())
+}
+
+fn encode(event: &Event) -> sse::Event {
+    sse::Event::default()
+        .event(event.name())
+        .json_data(event)
+        .expect("events serialize to JSON")
+}
@@ -0,0 +1,123 @@
+//! Atom feed of recently generated pages at `/feed.atom`, or of one domain with `?domain=`.
+use axum::extract::{Query, State};
+use axum::http::header::{CONTENT_TYPE, HOST};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::IntoResponse;
+use html_escape::{encode_double_quoted_attribute as attribute, encode_text as text};
+use serde::Deserialize;
+use std::time::SystemTime;
+use time::OffsetDateTime;
+use time::format_description::well_known::Rfc3339;
+
+use crate::AppState;
+use crate::vhost;
+
+/// Entries in a feed.
+const ENTRIES: usize = 50;
+
+#[derive(Deserialize)]
+pub struct FeedQuery {
+    domain: Option<String>,
+}
+
+pub async fn feed(
+    Query(FeedQuery { domain }): Query<FeedQuery>,
+    headers: HeaderMap,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, StatusCode> {
+    // Atom wants absolute IRIs, which depend on how we were reached
+    let host = headers
+        .get(HOST)
+        .and_then(|host| host.to_str().ok())
+        .ok_or(StatusCode::BAD_REQUEST)?;
+    let scheme = headers
+        .get("X-Forwarded-Proto")
+        .and_then(|proto| proto.to_str().ok())
+        .unwrap_or("http");
+    let base = format!("{scheme}://{host}");
+
+    let pages: Vec<_> = state
+        .search
+        .documents()
+        .into_iter()
+        .filter(|document| matches!(document.extension.as_str(), "html" | "htm"))
+        .filter(|document| {
+            domain
+                .as_ref()
+                .is_none_or(|domain| document.domain.eq_ignore_ascii_case(domain))
+        })
+        .take(ENTRIES)
+        .collect();
+
+    let (id, title, alternate) = match &domain {
+        Some(domain) => (
+            format!("{base}/feed.atom?domain={domain}"),
+            format!("web2050: {domain}"),
+            format!("{base}/?domain={domain}"),
+        ),
+        None => (
+            format!("{base}/feed.atom"),
+            "web2050".to_string(),
+            format!("{base}/"),
+        ),
+    };
+
+    let updated = pages
+        .first()
+        .map_or(SystemTime::UNIX_EPOCH, |page| page.created);
+
+    let mut xml = format!(
+        r#"<?xml version="1.0" encoding="utf-8"?>
+<feed xmlns="http://www.w3.org/2005/Atom">
+  <id>{}</id>
+  <title>{}</title>
+  <subtitle>Recently generated pages</subtitle>
+  <link rel="self" href="{}"/>
+  <link rel="alternate" href="{}"/>
+  <updated>{}</updated>
+  <generator>web2050</generator>
+"#,
+        text(&id),
+        text(&title),
+        attribute(&id),
+        attribute(&alternate),
+        timestamp(updated),
+    );
+
+    for page in pages {
+        // On the host of its domain with virtual hosts, which leaves out our port
+        let url = match vhost::link(state.vhosts.as_deref(), &page.path) {
+            link if link.starts_with("//") => format!("{scheme}:{link}"),
+            link => format!("{base}{link}"),
+        };
+
+        xml.push_str(&format!(
+            r#"  <entry>
+    <id>{}</id>
+    <title>{}</title>
+    <link rel="alternate" type="text/html" href="{}"/>
+    <published>{created}</published>
+    <updated>{created}</updated>
+    <author><name>{}</name></author>
+    <summary>{}</summary>
+  </entry>
+"#,
+            text(&url),
+            text(page.title.as_deref().unwrap_or(&page.path)),
+            attribute(&url),
+            text(&page.domain),
+            text(&page.summary),
+            created = timestamp(page.created),
+        ));
+    }
+
+    xml.push_str("</feed>\n");
+
+    Ok(([(CONTENT_TYPE, "application/atom+xml; charset=utf-8")], xml))
+}
+
+fn timestamp(time: SystemTime) -> String {
+    OffsetDateTime::from(time)
+        .format(&Rfc3339)
+        .unwrap_or_default()
+}
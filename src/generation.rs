@@ -0,0 +1,241 @@
+//! Bookkeeping for in-flight generations.
+//!
+//! Every file path has at most one generation running, whose output is shared through a [`Hub`].
+//! Different paths in the same domain run in parallel, up to a per-domain limit. A domain's
+//! semaphore only exists while someone holds or waits for one of its permits.
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use crate::hub::Hub;
+
+/// What happens to a generation once every client watching it has disconnected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DisconnectPolicy {
+    /// Keep going and cache the page for the next visitor.
+    Finish,
+    /// Cancel the upstream request to save model quota. Nothing is committed.
+    Abort,
+}
+
+impl FromStr for DisconnectPolicy {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, String> {
+        match value {
+            "finish" => Ok(Self::Finish),
+            "abort" => Ok(Self::Abort),
+            other => Err(format!(
+                "unknown disconnect policy `{other}`, expected one of: finish, abort"
+            )),
+        }
+    }
+}
+
+pub struct Generations {
+    in_flight: Mutex<HashMap<PathBuf, Arc<Hub>>>,
+    domains: Mutex<HashMap<OsString, Arc<Semaphore>>>,
+    domain_limit: usize,
+}
+
+pub enum Claim {
+    /// Nobody was generating the path; it is ours until the guard is dropped.
+    Owner(InFlight),
+    /// Someone else is; subscribe to their hub to follow along.
+    Joiner(Arc<Hub>),
+}
+
+/// A generation slot in a domain, given back when dropped.
+pub struct DomainPermit {
+    generations: Arc<Generations>,
+    domain: OsString,
+    permit: Option<OwnedSemaphorePermit>,
+}
+
+/// Marks a path as being generated for as long as it lives.
+pub struct InFlight {
+    generations: Arc<Generations>,
+    path: PathBuf,
+    hub: Arc<Hub>,
+}
+
+impl Generations {
+    pub fn new(domain_limit: usize) -> Self {
+        Self {
+            in_flight: Mutex::new(HashMap::new()),
+            domains: Mutex::new(HashMap::new()),
+            domain_limit,
+        }
+    }
+
+    pub fn claim(self: &Arc<Self>, path: &Path) -> Claim {
+        let mut in_flight = self.in_flight.lock().unwrap();
+
+        match in_flight.get(path) {
+            Some(hub) => Claim::Joiner(hub.clone()),
+            None => {
+                let hub = Arc::new(Hub::new());
+                in_flight.insert(path.to_path_buf(), hub.clone());
+                Claim::Owner(InFlight {
+                    generations: self.clone(),
+                    path: path.to_path_buf(),
+                    hub,
+                })
+            }
+        }
+    }
+
+    /// Paths being generated right now, with how many bytes of each were produced so far.
+    pub fn snapshot(&self) -> Vec<(PathBuf, usize)> {
+        self.in_flight
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(path, hub)| (path.clone(), hub.emitted_len()))
+            .collect()
+    }
+
+    /// Waits for a free generation slot in `domain`.
+    pub async fn domain_permit(self: &Arc<Self>, domain: &OsString) -> DomainPermit {
+        let semaphore = self
+            .domains
+            .lock()
+            .unwrap()
+            .entry(domain.clone())
+            .or_insert_with(|| Arc::new(Semaphore::new(self.domain_limit)))
+            .clone();
+
+        // Made first, so a waiter giving up cleans up after itself as well
+        let mut permit = DomainPermit {
+            generations: self.clone(),
+            domain: domain.clone(),
+            permit: None,
+        };
+
+        permit.permit = Some(
+            semaphore
+                .acquire_owned()
+                .await
+                .expect("domain semaphores are never closed"),
+        );
+        permit
+    }
+}
+
+impl InFlight {
+    pub fn hub(&self) -> &Arc<Hub> {
+        &self.hub
+    }
+}
+
+impl Drop for DomainPermit {
+    fn drop(&mut self) {
+        let mut domains = self.generations.domains.lock().unwrap();
+        drop(self.permit.take());
+
+        // Nobody else holds or waits for a permit, as they would own a clone, and with the
+        // lock held nobody can start to
+        if domains
+            .get(&self.domain)
+            .is_some_and(|semaphore| Arc::strong_count(semaphore) == 1)
+        {
+            domains.remove(&self.domain);
+        }
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        // Joiners of a generation that never got going must not wait forever
+        self.hub.finish(Err("generation was abandoned".to_string()));
+
+        self.generations
+            .in_flight
+            .lock()
+            .unwrap()
+            .remove(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::FutureExt;
+
+    #[tokio::test]
+    async fn one_owner_per_path() {
+        let generations = Arc::new(Generations::new(4));
+        let path = Path::new("example.com/index.html");
+
+        let Claim::Owner(owner) = generations.claim(path) else {
+            panic!("nobody was generating it");
+        };
+        let Claim::Joiner(hub) = generations.claim(path) else {
+            panic!("it is being generated");
+        };
+        assert!(Arc::ptr_eq(&hub, owner.hub()));
+
+        // Other paths are independent
+        assert!(matches!(
+            generations.claim(Path::new("example.com/about.html")),
+            Claim::Owner(_)
+        ));
+
+        owner.hub().send("<p>");
+        assert_eq!(generations.snapshot(), [(path.to_path_buf(), 3)]);
+
+        // Joiners of an owner that gives up get an error rather than waiting forever
+        let mut subscription = hub.subscribe();
+        drop(owner);
+        assert_eq!(subscription.recv().await.unwrap().unwrap(), "<p>");
+        assert!(subscription.recv().await.unwrap().is_err());
+        assert!(subscription.recv().await.is_none());
+
+        assert!(generations.snapshot().is_empty());
+        assert!(matches!(generations.claim(path), Claim::Owner(_)));
+    }
+
+    #[tokio::test]
+    async fn domains_are_limited() {
+        let generations = Arc::new(Generations::new(2));
+        let example = OsString::from("example.com");
+        let other = OsString::from("other.com");
+
+        let first = generations.domain_permit(&example).await;
+        let second = generations.domain_permit(&example).await;
+
+        let mut third = Box::pin(generations.domain_permit(&example));
+        assert!((&mut third).now_or_never().is_none());
+
+        // Other domains have slots of their own
+        let elsewhere = generations.domain_permit(&other).await;
+
+        drop(first);
+        let third = third.await;
+        assert!(generations.domain_permit(&example).now_or_never().is_none());
+
+        drop((second, third, elsewhere));
+        assert!(generations.domains.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn semaphores_go_away_with_their_last_permit() {
+        let generations = Arc::new(Generations::new(1));
+        let domain = OsString::from("example.com");
+
+        for _ in 0..3 {
+            drop(generations.domain_permit(&domain).await);
+            assert!(generations.domains.lock().unwrap().is_empty());
+        }
+
+        // Also when the last one waiting gives up
+        let held = generations.domain_permit(&domain).await;
+        let waiting = generations.domain_permit(&domain).now_or_never();
+        assert!(waiting.is_none());
+        drop(held);
+        assert!(generations.domains.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,218 @@
+//! Fan-out of one in-progress generation to every client viewing it.
+//!
+//! The hub remembers everything emitted so far, so a client joining late first receives the
+//! prefix and then the live tail, ending up with exactly what the first client saw.
+use std::sync::{Arc, Mutex};
+use tokio::sync::{Notify, mpsc};
+
+type Message = Result<String, String>;
+
+#[derive(Default)]
+pub struct Hub {
+    inner: Mutex<Inner>,
+    /// Notified when the last subscription is dropped.
+    idle: Notify,
+}
+
+#[derive(Default)]
+struct Inner {
+    emitted: String,
+    subscribers: Vec<mpsc::UnboundedSender<Message>>,
+    /// Subscriptions that have not been dropped, i.e. clients still connected.
+    live: usize,
+    /// `Some` once the generation is over, holding its error if it failed.
+    outcome: Option<Result<(), String>>,
+}
+
+pub struct Subscription {
+    rx: mpsc::UnboundedReceiver<Message>,
+    hub: Arc<Hub>,
+}
+
+impl Hub {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn subscribe(self: &Arc<Self>) -> Subscription {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let mut inner = self.inner.lock().unwrap();
+        inner.live += 1;
+
+        if !inner.emitted.is_empty() {
+            let _ = tx.send(Ok(inner.emitted.clone()));
+        }
+
+        match &inner.outcome {
+            None => inner.subscribers.push(tx),
+            Some(Ok(())) => {}
+            Some(Err(reason)) => {
+                let _ = tx.send(Err(reason.clone()));
+            }
+        }
+
+        Subscription {
+            rx,
+            hub: self.clone(),
+        }
+    }
+
+    pub fn send(&self, chunk: &str) {
+        if chunk.is_empty() {
+            return;
+        }
+
+        let mut inner = self.inner.lock().unwrap();
+        inner.emitted.push_str(chunk);
+
+        // Clients that left are dropped here
+        inner
+            .subscribers
+            .retain(|tx| tx.send(Ok(chunk.to_string())).is_ok());
+    }
+
+    pub fn emitted_len(&self) -> usize {
+        self.inner.lock().unwrap().emitted.len()
+    }
+
+    /// Ends every subscription, with an error if the generation failed. Only the first call has
+    /// any effect.
+    pub fn finish(&self, outcome: Result<(), String>) {
+        let mut inner = self.inner.lock().unwrap();
+
+        if inner.outcome.is_some() {
+            return;
+        }
+
+        if let Err(reason) = &outcome {
+            for tx in &inner.subscribers {
+                let _ = tx.send(Err(reason.clone()));
+            }
+        }
+
+        inner.subscribers.clear();
+        inner.outcome = Some(outcome);
+    }
+
+    /// Resolves once every subscription has been dropped.
+    pub async fn abandoned(&self) {
+        loop {
+            let notified = self.idle.notified();
+            tokio::pin!(notified);
+            notified.as_mut().enable();
+
+            if self.inner.lock().unwrap().live == 0 {
+                return;
+            }
+
+            notified.await;
+        }
+    }
+}
+
+impl Subscription {
+    pub async fn recv(&mut self) -> Option<std::io::Result<String>> {
+        let message = self.rx.recv().await?;
+        Some(message.map_err(std::io::Error::other))
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        let mut inner = self.hub.inner.lock().unwrap();
+        inner.live -= 1;
+
+        if inner.live == 0 {
+            self.hub.idle.notify_waiters();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::FutureExt;
+
+    async fn drain(mut subscription: Subscription) -> (String, Option<String>) {
+        let mut received = String::new();
+
+        while let Some(message) = subscription.recv().await {
+            match message {
+                Ok(chunk) => received.push_str(&chunk),
+                Err(e) => return (received, Some(e.to_string())),
+            }
+        }
+
+        (received, None)
+    }
+
+    #[tokio::test]
+    async fn late_joiners_get_the_prefix() {
+        let hub = Arc::new(Hub::new());
+        let early = hub.subscribe();
+
+        hub.send("<p>");
+        hub.send("");
+        hub.send("hello");
+        let late = hub.subscribe();
+        hub.send("</p>");
+        hub.finish(Ok(()));
+
+        // Even after the end
+        let after = hub.subscribe();
+
+        assert_eq!(hub.emitted_len(), "<p>hello</p>".len());
+        for subscription in [early, late, after] {
+            assert_eq!(
+                drain(subscription).await,
+                ("<p>hello</p>".to_string(), None)
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn failures_reach_every_subscriber() {
+        let hub = Arc::new(Hub::new());
+        let during = hub.subscribe();
+
+        hub.send("<p>");
+        hub.finish(Err("the model went away".to_string()));
+        // Only the first outcome counts
+        hub.finish(Ok(()));
+
+        let after = hub.subscribe();
+
+        for subscription in [during, after] {
+            let (received, error) = drain(subscription).await;
+            assert_eq!(received, "<p>");
+            assert_eq!(error.as_deref(), Some("the model went away"));
+        }
+    }
+
+    #[tokio::test]
+    async fn abandoned_once_the_last_subscriber_leaves() {
+        let hub = Arc::new(Hub::new());
+
+        // Nobody watching at all
+        assert!(hub.abandoned().now_or_never().is_some());
+
+        let first = hub.subscribe();
+        let second = hub.subscribe();
+
+        let abandoned = hub.abandoned();
+        tokio::pin!(abandoned);
+        assert!(abandoned.as_mut().now_or_never().is_none());
+
+        drop(first);
+        assert!(abandoned.as_mut().now_or_never().is_none());
+
+        // Finishing doesn't count as leaving
+        hub.finish(Ok(()));
+        assert!(abandoned.as_mut().now_or_never().is_none());
+
+        drop(second);
+        tokio::time::timeout(std::time::Duration::from_secs(1), abandoned)
+            .await
+            .unwrap();
+    }
+}
@@ -3,219 +3,675 @@
 use async_stream::stream;
 
 use axum::body::Body;
-use axum::extract::{Query, State};
+use axum::extract::{Form, Query, State};
 use axum::handler::HandlerWithoutStateExt;
-use axum::http::{HeaderValue, Request, Response, StatusCode, Uri};
+use axum::http::header::REFERER;
+use axum::http::{HeaderMap, HeaderValue, Method, Request, Response, StatusCode, Uri};
 use axum::middleware::Next;
 use axum::response::IntoResponse;
 use axum::routing::get;
 use axum::{Router, middleware};
 
+use mime_guess::Mime;
 use std::collections::HashMap;
-use std::ffi::OsString;
-use std::io::BufRead;
-use std::str::FromStr;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use tokio::fs::{self, File};
-use tokio::sync::{Mutex, Notify, mpsc};
+use std::time::Instant;
+use time::OffsetDateTime;
+use time::format_description::well_known::Rfc3339;
+use tokio::fs;
+use tokio::sync::oneshot;
 
+use dotenvy::EnvMap;
+
+use crate::backend::{CompletionEvent, FinishReason, LlmBackend, Usage};
+use crate::cli::{Cli, Command, Options, ServeArgs};
+use crate::config::Config;
+use crate::events::{Event, Events};
+use crate::generation::{Claim, DisconnectPolicy, Generations, InFlight};
+use crate::hub::Subscription;
+use crate::meta::Metadata;
+use crate::profile::Profiles;
+use crate::prompt::Prompts;
+use crate::search::SearchIndex;
+use crate::store::Store;
+use crate::variants::{Source, Variant, Variants};
+use crate::vhost::VirtualHosts;
+
+mod admin;
 mod ai;
+mod api;
 mod assets;
+mod backend;
+mod browse;
+mod canonical;
+mod cli;
+mod config;
+mod context;
+mod events;
+mod feed;
+mod generation;
+mod hub;
+mod meta;
+mod profile;
+mod prompt;
+mod rewrite;
+mod search;
+mod sse;
+mod store;
 mod streaming_parser;
+mod variants;
+mod vhost;
 
-type GenerationMap = Arc<Mutex<HashMap<OsString, Arc<Notify>>>>;
+#[derive(Clone)]
+struct AppState {
+    generations: Arc<Generations>,
+    backend: Arc<dyn LlmBackend>,
+    store: Arc<Store>,
+    search: Arc<SearchIndex>,
+    events: Arc<Events>,
+    prompts: Arc<Prompts>,
+    profiles: Arc<Profiles>,
+    disconnect: DisconnectPolicy,
+    context_budget: usize,
+    asset_limits: assets::Limits,
+    variants: Arc<Variants>,
+    cache_forms: bool,
+    vhosts: Option<Arc<VirtualHosts>>,
+}
 
 async fn generate(
     url: Uri,
-    State(gen_map): State<GenerationMap>,
-) -> Result<impl IntoResponse, StatusCode> {
-    use crate::ai::AIResponse;
-    use crate::streaming_parser::StreamingParser;
-    use futures_util::TryStreamExt;
-    use mime_guess::Mime;
-    use std::path::Path;
-    use std::path::PathBuf;
-    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufWriter};
-    use tokio_util::io::StreamReader;
+    headers: HeaderMap,
+    State(state): State<AppState>,
+) -> Result<Response<Body>, StatusCode> {
+    let referer = referer(&headers, state.vhosts.as_deref());
 
-    let url = url.path();
-    let url = url.strip_prefix('/').unwrap_or(url);
-    let url = PathBuf::from(url);
+    generate_path(
+        state,
+        url.path(),
+        url.query(),
+        referer.as_deref(),
+        None,
+        false,
+    )
+    .await
+}
 
-    let extension = url.extension().and_then(|x| x.to_str());
+/// Responds to a form posted to `url` with a page generated for the submitted fields and the
+/// query parameters of `url`. Only URL-encoded forms are supported, `multipart/form-data` ones
+/// are rejected with a 415 by the `Form` extractor.
+async fn submit(
+    url: Uri,
+    headers: HeaderMap,
+    State(state): State<AppState>,
+    Form(fields): Form<Vec<(String, String)>>,
+) -> Result<Response<Body>, StatusCode> {
+    let referer = referer(&headers, state.vhosts.as_deref());
 
-    if let Some("map") = extension {
-        return Err(StatusCode::BAD_REQUEST);
-    }
+    generate_path(
+        state,
+        url.path(),
+        url.query(),
+        referer.as_deref(),
+        Some(&fields),
+        false,
+    )
+    .await
+}
 
-    let (url, extension) = match (url.components().count(), extension) {
-        (1, _) | (_, None) => (url.join("index.html"), "html"),
-        (_, Some(ext)) => (url.clone(), ext),
+/// The path of the page the visitor came from, which is the best context for the one they are
+/// going to.
+fn referer(headers: &HeaderMap, vhosts: Option<&VirtualHosts>) -> Option<String> {
+    let referer = headers.get(REFERER)?.to_str().ok()?.parse::<Uri>().ok()?;
+
+    Some(match vhosts {
+        Some(vhosts) => vhosts.path(&referer),
+        None => referer.path().to_string(),
+    })
+}
+
+/// A path claimed for generation by a request.
+struct Job {
+    in_flight: InFlight,
+    page: PathBuf,
+    /// Where the output goes, `page` or a variant of it.
+    url: PathBuf,
+    mime_type: Mime,
+    variant: Option<Variant>,
+    /// Whether the output is committed, which form responses only are with
+    /// `CACHE_FORM_RESPONSES`.
+    cache: bool,
+    referer: Option<PathBuf>,
+}
+
+/// Streams the generation of `path`, requested with `query` or by submitting `form` from the
+/// page at `referer`. Unless `force` is set, an existing file is served instead. Query
+/// parameters selected by `QUERY_PARAMS` and forms, together with the query they were posted
+/// with, get a variant of the page of their own, form responses are only kept with
+/// `CACHE_FORM_RESPONSES`.
+async fn generate_path(
+    state: AppState,
+    path: &str,
+    query: Option<&str>,
+    referer: Option<&str>,
+    form: Option<&[(String, String)]>,
+    force: bool,
+) -> Result<Response<Body>, StatusCode> {
+    let AppState {
+        generations,
+        store,
+        variants,
+        cache_forms,
+        ..
+    } = &state;
+
+    let (page, mime_type) = store.resolve(path)?;
+    let variant = match form {
+        Some(fields) => Some(variants.form(query, fields)?),
+        None => variants.select(query)?,
     };
 
-    if url.as_os_str().len() > 72 {
-        return Err(StatusCode::URI_TOO_LONG);
+    let url = match &variant {
+        Some(variant) => variant.path(&page),
+        None => page.clone(),
+    };
+
+    let cache = variant
+        .as_ref()
+        .is_none_or(|variant| variant.source != Source::Form || *cache_forms);
+
+    let in_flight = match generations.claim(&url) {
+        Claim::Owner(in_flight) => in_flight,
+        // Follow the generation already running instead of calling the model again
+        Claim::Joiner(hub) => return Ok(stream_subscription(hub.subscribe(), &mime_type)),
+    };
+
+    // It may have been committed between ServeDir looking and us claiming it
+    if cache
+        && !force
+        && let Some(response) = serve_existing(&store.path(&url), &mime_type, &in_flight).await
+    {
+        return Ok(response);
+    }
+
+    // Its last output failed the profile's checks, which the model would most likely repeat
+    if cache && !force && store.rejection(&url).await.is_some() {
+        return Err(StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    if cache && variant.is_some() && variants.full(store.root(), &url).await {
+        return Err(StatusCode::INSUFFICIENT_STORAGE);
     }
 
+    let referer = referer
+        .and_then(|referer| store.resolve(referer).ok())
+        .map(|(referer, _)| referer);
+
+    let subscription = in_flight.hub().subscribe();
+    let (ready, connected) = oneshot::channel();
+
+    // Waiting for a slot, reading the domain and reaching the model take a while. A client
+    // leaving meanwhile must not abandon the generation for everyone who joined it.
+    tokio::spawn(run(
+        state.clone(),
+        Job {
+            in_flight,
+            page,
+            url,
+            mime_type: mime_type.clone(),
+            variant,
+            cache,
+            referer,
+        },
+        ready,
+    ));
+
+    // Until the model answers, failures still get a status of their own
+    if let Ok(Err(status)) = connected.await {
+        return Err(status);
+    }
+
+    Ok(stream_subscription(subscription, &mime_type))
+}
+
+/// Generates the file claimed by `job`, reporting through `ready` once the model answered or
+/// with the status to respond with if it couldn't be asked.
+async fn run(
+    AppState {
+        generations,
+        backend,
+        store,
+        search,
+        events: feed,
+        prompts,
+        profiles,
+        disconnect,
+        context_budget,
+        asset_limits,
+        vhosts,
+        ..
+    }: AppState,
+    Job {
+        in_flight,
+        page,
+        url,
+        mime_type,
+        variant,
+        cache,
+        referer,
+    }: Job,
+    ready: oneshot::Sender<Result<(), StatusCode>>,
+) {
+    use crate::rewrite::LinkRewriter;
+    use crate::streaming_parser::StreamingParser;
+    use futures_util::StreamExt;
+
     let key = url
         .iter()
         .next()
         .expect("cannot reach generator with no parent")
         .to_os_string();
 
-    let (we_are_not_the_inserter, notifier) = {
-        let mut map = gen_map.lock().await;
+    let _permit = generations.domain_permit(&key).await;
+    let fs_domain = store.path(&key);
 
-        match map.get(&key) {
-            Some(existing_notifier) => (true, existing_notifier.clone()),
-            None => {
-                let new_notifier = Arc::new(Notify::new());
-                map.insert(key.clone(), new_notifier.clone());
-                (false, new_notifier)
-            }
-        }
-    };
+    feed.send(Event::started(&url));
 
-    if we_are_not_the_inserter {
-        notifier.notified().await;
-    }
+    // Fetch all assets relating to the domain. Files still being generated by the other slots of
+    // this domain are not committed yet and therefore missing.
+    let assets = assets::read_all_files_in_dir(&fs_domain, asset_limits).await;
 
-    // Regardless of whether the current folder was being generated on, we must still generate
-    // this one.
+    let skipped_assets: Vec<String> = assets
+        .skipped
+        .iter()
+        .map(|skipped| {
+            let path = skipped
+                .path
+                .strip_prefix(store.root())
+                .unwrap_or(&skipped.path);
+            format!("{} ({})", path.display(), skipped.reason)
+        })
+        .collect();
 
-    let fs_path = Path::new("internet").join(&url);
-    let fs_domain = Path::new("internet").join(&key);
-    let parent_fs_path = fs_path
-        .parent()
-        .expect("cannot reach generator with empty path");
+    // Only as much of them as fits the budget, the most relevant first
+    let context = context::build(
+        assets,
+        store.root(),
+        &page,
+        referer.as_deref(),
+        context_budget,
+        vhosts.is_some(),
+    );
 
-    // Create all the folders
-    fs::create_dir_all(&parent_fs_path).await.map_err(|e| {
-        eprintln!("{e}");
-        StatusCode::INTERNAL_SERVER_ERROR
-    })?;
-
-    // Fetch all assets relating to the domain. We should wait, but it is unlikely that a request
-    // to a/b happens while a request to a is already happening.
-    //
-    // This gives the AI WAAAY more context though. We could not include content for files not in
-    // the current route, and only include an abstract tree..
-    let assets = assets::read_all_files_in_dir(&fs_domain)
-        .await
-        .map_err(|e| {
-            eprintln!("{e}");
-            StatusCode::INTERNAL_SERVER_ERROR
-        })?;
+    let asset_paths: Vec<String> = context
+        .files
+        .iter()
+        .map(|file| file.path.trim_start_matches('/').to_string())
+        .collect();
 
-    let stream = ai::stream_page_ndjson(&url, assets).await.map_err(|e| {
-        eprintln!("{e}");
-        StatusCode::INTERNAL_SERVER_ERROR
-    })?;
+    let started = Instant::now();
+    let created = OffsetDateTime::now_utc();
 
-    let mut lines = StreamReader::new(stream.bytes_stream().map_err(std::io::Error::other)).lines();
+    let profile = profiles.select(&page, &mime_type).clone();
+    let options = profile.request_options();
 
-    let file = File::create(Path::new("internet").join(url))
-        .await
-        .map_err(|e| {
-            eprintln!("{e}");
-            StatusCode::INTERNAL_SERVER_ERROR
-        })?;
+    let prompt = match prompts.render(&page, variant.as_ref(), &context, &profile) {
+        Ok(prompt) => prompt,
+        Err(e) => {
+            return not_started(
+                &feed,
+                &in_flight,
+                &url,
+                e,
+                ready,
+                StatusCode::INTERNAL_SERVER_ERROR,
+            );
+        }
+    };
+
+    let mut events = match ai::stream_page_ndjson(&*backend, &prompt, &options).await {
+        Ok(events) => events,
+        Err(e) => return not_started(&feed, &in_flight, &url, e, ready, StatusCode::BAD_GATEWAY),
+    };
+
+    // Form responses that are not kept only go to the client
+    let mut file = match cache {
+        true => match store.create(&url).await {
+            Ok(file) => Some(file),
+            Err(e) => {
+                return not_started(
+                    &feed,
+                    &in_flight,
+                    &url,
+                    e,
+                    ready,
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                );
+            }
+        },
+        false => None,
+    };
+
+    let _ = ready.send(Ok(()));
 
-    let (tx, mut rx) = mpsc::channel::<String>(32);
+    let mut rewriter = LinkRewriter::new(&page, &mime_type, vhosts.as_deref());
 
-    tokio::spawn(async move {
-        let mut writer = BufWriter::new(file);
+    // Output that has to pass checks is held back until it did, so none of it runs otherwise
+    let checked = profile.checks();
 
-        let mut parser = StreamingParser::new();
+    let mut parser = StreamingParser::new();
+    let mut finish = None;
+    let mut usage: Option<Usage> = None;
+    let mut failure = None;
+    let mut first_token = None;
+    let mut bytes = 0;
+    let mut content = String::new();
+    let mut last_progress = Instant::now();
 
-        while let Ok(Some(line)) = lines.next_line().await {
-            if line.len() < 6 {
+    loop {
+        let event = tokio::select! {
+            event = events.next() => event,
+            _ = in_flight.hub().abandoned(), if disconnect == DisconnectPolicy::Abort => {
+                failure = Some("cancelled, every client disconnected".to_string());
+                break;
+            }
+        };
+
+        let Some(event) = event else { break };
+
+        let chunk = match event {
+            Ok(CompletionEvent::Delta(chunk)) => {
+                first_token.get_or_insert_with(|| started.elapsed());
+                rewriter.feed(&parser.feed(&chunk))
+            }
+            Ok(CompletionEvent::Finish(reason)) => {
+                finish = Some(reason);
                 continue;
             }
+            Ok(CompletionEvent::Usage(u)) => {
+                usage = Some(usage.unwrap_or_default().merge(u));
+                continue;
+            }
+            Ok(CompletionEvent::Error(e)) => {
+                failure = Some(format!("upstream error: {e}"));
+                break;
+            }
+            Err(e) => {
+                failure = Some(format!("stream error: {e}"));
+                break;
+            }
+        };
+
+        if let Some(file) = &mut file
+            && let Err(e) = file.write(&chunk).await
+        {
+            failure = Some(format!("write error: {e}"));
+            break;
+        }
+
+        if !checked {
+            in_flight.hub().send(&chunk);
+        }
+
+        content.push_str(&chunk);
+        bytes += chunk.len();
+        if last_progress.elapsed() >= events::PROGRESS_INTERVAL {
+            last_progress = Instant::now();
+            feed.send(Event::Progress {
+                path: url.to_string_lossy().into_owned(),
+                bytes,
+            });
+        }
+    }
 
-            // 6.. ignores data: or fails and skips when the response is `\n`
-            if let Ok(json) = serde_json::from_str::<AIResponse>(&line[6..]) {
-                if let Some(choice) = json.choices.first() {
-                    if let Some(delta) = choice.delta.as_ref() {
-                        if let Some(chunk) = &delta.content {
-                            let chunk = parser.feed(chunk);
+    // Dropping the stream closes the upstream connection
+    drop(events);
 
-                            writer.write_all(chunk.as_bytes()).await.unwrap();
+    // Whatever the rewriter held back for a tag or rule that never completed
+    let rest = rewriter.finish();
+    if failure.is_none() && !rest.is_empty() {
+        if let Some(file) = &mut file
+            && let Err(e) = file.write(&rest).await
+        {
+            failure = Some(format!("write error: {e}"));
+        }
+
+        if !checked {
+            in_flight.hub().send(&rest);
+        }
+        content.push_str(&rest);
+        bytes += rest.len();
+    }
+
+    let failure = failure
+        .or(match finish {
+            Some(FinishReason::Stop) => None,
+            Some(reason) => Some(format!("finished early ({reason})")),
+            None => Some("stream ended without a finish reason".to_string()),
+        })
+        .or_else(|| (!parser.is_closed()).then(|| "output has no closing </_out> tag".to_string()));
+
+    // Unchecked output was streamed raw, but only the processed file is kept
+    let mut rejected = false;
+    let failure = match failure {
+        None => match profile.post_process(content.clone()) {
+            Ok(processed) => {
+                if checked {
+                    in_flight.hub().send(&processed);
+                }
 
-                            // This fails if the user leave since nothing is recieving.
-                            let _ = tx.send(chunk.to_string()).await;
+                match &mut file {
+                    Some(file) if processed != content => {
+                        bytes = processed.len();
+                        file.rewrite(&processed)
+                            .await
+                            .err()
+                            .map(|e| format!("write error: {e}"))
+                    }
+                    _ => None,
+                }
+            }
+            Err(e) => {
+                rejected = true;
+                Some(format!("rejected by {e}"))
+            }
+        },
+        failure => failure,
+    };
+
+    let failure = match failure {
+        Some(reason) => {
+            if let Some(file) = file {
+                let result = match rejected {
+                    true => file.reject(&reason).await,
+                    false => file.abandon().await,
+                };
+                if let Err(e) = result {
+                    eprintln!("{e}");
+                }
+            }
+            Some(reason)
+        }
+        None => match file {
+            Some(file) => {
+                let usage = usage.unwrap_or_default();
+                let metadata = Metadata {
+                    path: url.to_string_lossy().into_owned(),
+                    query: variant
+                        .as_ref()
+                        .and_then(Variant::selected)
+                        .map(|variant| variant.query.clone()),
+                    version: 0,
+                    backend: backend.name().to_string(),
+                    model: options.model.or(backend.model().map(str::to_string)),
+                    prompt_version: prompt.version,
+                    profile: profile.name,
+                    created: created.format(&Rfc3339).unwrap_or_default(),
+                    prompt_tokens: usage.prompt_tokens,
+                    completion_tokens: usage.completion_tokens,
+                    latency_ms: started.elapsed().as_millis() as u64,
+                    first_token_ms: first_token.map(|t| t.as_millis() as u64),
+                    finish_reason: FinishReason::Stop.to_string(),
+                    assets: asset_paths,
+                    skipped_assets,
+                };
+
+                match file.commit(metadata).await {
+                    Ok(version) => {
+                        // Variants are hidden, like they are when the index is built
+                        if url == page {
+                            search.refresh(&store, &url).await;
                         }
+                        feed.send(Event::Committed {
+                            path: url.to_string_lossy().into_owned(),
+                            version,
+                            bytes,
+                        });
+                        None
                     }
+                    Err(e) => Some(format!("commit error: {e}")),
                 }
             }
+            // A form response that is not kept
+            None => None,
+        },
+    };
+
+    match failure {
+        // Nothing was committed, so fail the body to let the browser know the page is
+        // incomplete.
+        Some(reason) => {
+            eprintln!("generating {} failed: {reason}", url.display());
+            feed.send(Event::failed(&url, &reason));
+            in_flight.hub().finish(Err(reason));
         }
+        None => {
+            in_flight.hub().finish(Ok(()));
 
-        writer.flush().await.unwrap();
+            if let Some(Usage {
+                prompt_tokens: Some(prompt),
+                completion_tokens: Some(completion),
+            }) = usage
+            {
+                eprintln!(
+                    "generated {} ({prompt} prompt + {completion} completion tokens)",
+                    url.display()
+                );
+            }
+        }
+    }
+}
 
-        let mut guard = gen_map.lock().await;
-        guard.remove(&key);
-        notifier.notify_waiters();
-    });
+/// Ends a generation that failed before the model was asked.
+fn not_started(
+    feed: &Events,
+    in_flight: &InFlight,
+    url: &Path,
+    error: impl std::fmt::Display,
+    ready: oneshot::Sender<Result<(), StatusCode>>,
+    status: StatusCode,
+) {
+    eprintln!("{error}");
+    feed.send(Event::failed(url, &error));
+    in_flight.hub().finish(Err(error.to_string()));
+    let _ = ready.send(Err(status));
+}
 
+fn stream_subscription(mut subscription: Subscription, mime_type: &Mime) -> Response<Body> {
     let stream = stream! {
-        while let Some(delta) = rx.recv().await {
-            yield Ok::<String, std::convert::Infallible>(delta);
+        while let Some(delta) = subscription.recv().await {
+            yield delta;
         }
     };
 
-    // Must default to HTML because .com is technically an extension
-    let mime_type = mime_guess::from_ext(extension).first_or(Mime::from_str("text/html").unwrap());
-
-    Ok(Response::builder()
+    Response::builder()
         .header("Content-Type", mime_type.as_ref())
         .body(Body::from_stream(stream))
-        .unwrap())
+        .unwrap()
 }
 
-async fn index(Query(params): Query<HashMap<String, String>>) -> Result<Body, StatusCode> {
-    use std::env::current_dir;
-    use std::process::{Command, Stdio};
+/// Responds with an already generated file, if there is one. Requests that joined `in_flight`
+/// meanwhile get it as well, instead of the error of an abandoned generation.
+async fn serve_existing(
+    path: &Path,
+    mime_type: &Mime,
+    in_flight: &InFlight,
+) -> Option<Response<Body>> {
+    let content = fs::read(path).await.ok()?;
+
+    // Generated files are text
+    in_flight.hub().send(&String::from_utf8_lossy(&content));
+    in_flight.hub().finish(Ok(()));
+
+    Some(
+        Response::builder()
+            .header("Content-Type", mime_type.as_ref())
+            .body(Body::from(content))
+            .unwrap(),
+    )
+}
 
-    let cwd = current_dir()
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
-        .join("internet");
+async fn index(
+    Query(params): Query<HashMap<String, String>>,
+    State(state): State<AppState>,
+) -> Result<Body, StatusCode> {
+    // Drilling into one domain, and restricting searches to it
+    let domain = params.get("domain").filter(|d| !d.is_empty()).cloned();
 
-    let query = params.get("q");
+    // Terms, "exact phrases", domain:example.com and ext:html
+    let query = params
+        .get("q")
+        .map(|q| search::Query::parse(q).with_domain(domain.clone()))
+        .filter(|q| !q.is_empty());
     let content = query.is_some();
 
-    let mut rg = Command::new("rg");
+    let pages = state.search.len();
 
-    let mut rg = if let Some(term) = query {
-        rg.arg("-i").arg(term)
-    } else {
-        rg.arg("--files")
-    }
-    .arg("--no-ignore-vcs")
-    .arg("--sortr=created") // New at top
-    .current_dir(cwd)
-    .stdout(Stdio::piped())
-    .spawn()
-    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let (breadcrumb, scope) = match &domain {
+        Some(domain) => (
+            format!(
+                r#"<p class="mb-4 text-gray-400"><a href="/" class="text-blue-500 hover:underline">All domains</a> / {}</p>"#,
+                html_escape::encode_text(domain)
+            ),
+            format!(
+                r#"<input type="hidden" name="domain" value="{}"/>"#,
+                html_escape::encode_double_quoted_attribute(domain)
+            ),
+        ),
+        None => Default::default(),
+    };
+
+    // Generations link to their own host, like the pages below
+    let live = match &state.vhosts {
+        Some(vhosts) => format!(
+            r#" data-suffix="{}""#,
+            html_escape::encode_double_quoted_attribute(vhosts.suffix())
+        ),
+        None => String::new(),
+    };
 
     let stream = stream! {
         // Head
-        yield Ok::<_, std::convert::Infallible>(r#"<!DOCTYPE html>
+        yield Ok::<_, std::convert::Infallible>(format!(r#"<!DOCTYPE html>
 <html lang="en" class="dark">
 <head>
   <meta charset="UTF-8"/>
   <meta name="viewport" content="width=device-width,initial-scale=1.0"/>
   <title>web2050</title>
   <link rel="stylesheet" href="/style.css">
+  <link rel="alternate" type="application/atom+xml" title="web2050" href="/feed.atom">
 </head>
 <body class="bg-gray-950 text-gray-100 min-h-screen flex items-center justify-center px-4 py-8">
   <main class="w-full max-w-2xl">
     <header class="mb-8 text-center">
       <h1 class="text-4xl font-bold text-blue-500">web2050 Index</h1>
       <p class="text-gray-400 mt-2">Append any URL minus the protocol (https://) to the end of this URL and watch AI generate it in real time.</p>
-      <p class="text-gray-400 mt-2">Search the index of all AI-generated pages sorted descending by creation. <span id="counter">0</span> pages have been generated so far.</p>
+      <p class="text-gray-400 mt-2">Browse the AI-generated domains or search all of their pages. <span id="counter">{pages}</span> pages have been generated so far.</p>
     </header>
     <section class="mb-6 w-full flex space-x-2">
       <form method="get" class="flex w-full">
+        {scope}
         <input
           type="text"
           name="q"
@@ -228,27 +684,68 @@ async fn index(Query(params): Query<HashMap<String, String>>) -> Result<Body, St
         </button>
       </form>
     </section>
-    <ul id="list" class="space-y-2">"#.to_string());
+    <section id="live" class="mb-6 hidden">
+      <h2 class="text-lg font-semibold text-gray-300 mb-2">Generating now</h2>
+      <ul id="live-list" class="space-y-1"{live}></ul>
+    </section>
+    {breadcrumb}
+    <ul id="list" class="space-y-2">"#));
 
     yield Ok(r#"<script>
-  const ul = document.getElementById("list");
   const counter = document.getElementById("counter");
+  const live = document.getElementById("live");
+  const liveList = document.getElementById("live-list");
+  const rows = new Map();
 
-  // counter.textContent = ul.querySelectorAll("li").length;
-
-  const observer = new MutationObserver((mutationsList) => {
-    let newItems = 0;
-    for (const mutation of mutationsList) {
-      if (mutation.type === 'childList') {
-        newItems += mutation.addedNodes.length;
-      }
-    }
-    if (newItems > 0) {
-      counter.textContent = ul.querySelectorAll("li").length;
+  // Status line of a generation, added on first sight
+  function status(path) {
+    let row = rows.get(path);
+    if (!row) {
+      row = document.createElement("li");
+      const link = document.createElement("a");
+      // On the host of its domain with virtual hosts
+      const suffix = liveList.dataset.suffix;
+      const [domain, ...rest] = path.split("/");
+      link.href = suffix ? `//${domain}${suffix}/${rest.join("/")}` : "/" + path;
+      link.textContent = path;
+      link.className = "text-blue-500 hover:underline";
+      const text = document.createElement("span");
+      text.className = "text-xs text-gray-500 ml-2";
+      row.append(link, text);
+      liveList.prepend(row);
+      rows.set(path, row);
     }
-  });
+    live.classList.remove("hidden");
+    return row.lastChild;
+  }
+
+  function done(path) {
+    setTimeout(() => {
+      rows.get(path)?.remove();
+      rows.delete(path);
+      if (rows.size === 0) live.classList.add("hidden");
+    }, 10000);
+  }
 
-  observer.observe(ul, { childList: true });
+  const events = new EventSource("/_events");
+  events.addEventListener("started", e => {
+    status(JSON.parse(e.data).path).textContent = "starting...";
+  });
+  events.addEventListener("progress", e => {
+    const { path, bytes } = JSON.parse(e.data);
+    status(path).textContent = `${bytes} bytes`;
+  });
+  events.addEventListener("committed", e => {
+    const { path, bytes, version } = JSON.parse(e.data);
+    status(path).textContent = `done, ${bytes} bytes (version ${version})`;
+    if (version === 1) counter.textContent = Number(counter.textContent) + 1;
+    done(path);
+  });
+  events.addEventListener("failed", e => {
+    const { path, reason } = JSON.parse(e.data);
+    status(path).textContent = `failed: ${reason}`;
+    done(path);
+  });
 
     document.addEventListener("DOMContentLoaded", () => {
       const input = document.getElementById("search-input");
@@ -256,8 +753,8 @@ async fn index(Query(params): Query<HashMap<String, String>>) -> Result<Body, St
       input.addEventListener("input", () => {
         const q = input.value.toLowerCase();
         items.forEach(el => {
-          const path = el.children[0].href.slice(1);
-          if (path && path.toLowerCase().includes(q)) {
+          const path = el.querySelector("a")?.getAttribute("href") ?? "";
+          if (el.querySelector("details") || path.toLowerCase().includes(q)) {
             el.removeAttribute("style");
           } else {
             el.style.display = "none";
@@ -276,26 +773,37 @@ padding: calc(var(--spacing) * 2);
 overflow-wrap: break-word;
 white-space: pre-wrap;
 color: var(--color-gray-100);
+}
+mark {
+background-color: var(--color-blue-500);
+color: var(--color-gray-100);
 }</style>"#.to_string());
     }
 
-    if let Some(stdout) = rg.stdout.take() {
-        let reader = std::io::BufReader::new(stdout);
+    if let Some(query) = query {
+        for hit in state.search.search(&query) {
+            let href = vhost::link(state.vhosts.as_deref(), &hit.document.path);
+            let href = html_escape::encode_double_quoted_attribute(&href);
+            let path = html_escape::encode_text(&hit.document.path);
+            let snippet: String = hit
+                .snippets
+                .iter()
+                .map(|s| format!("<pre><code>{}</code></pre>", s.to_html()))
+                .collect();
 
-        for line in reader.lines().map_while(Result::ok) {
-            if content {
-                if let Some((path, snippet)) = line.split_once(':') {
-                    yield Ok(format!(
-                        r#"<li><a href="/{path}">{path}</a><pre><code>{0}</code></pre></li>"#,
-                        html_escape::encode_text(snippet)
-                    ));
-                }
-            } else {
-                yield Ok(format!(
-                    r#"<li><a href="/{line}">{line}</a></li>"#
-                ));
-            }
+            yield Ok(format!(r#"<li><a href="{href}">{path}</a>{snippet}</li>"#));
+        }
+    } else if let Some(domain) = domain {
+        match browse::tree(&state.search, &state.store, state.vhosts.as_deref(), &domain).await {
+            Some(tree) => yield Ok(tree),
+            None => yield Ok(format!(
+                r#"<li class="text-gray-400">Nothing generated yet, <a href="{}" class="text-blue-500 hover:underline">visit {}</a> to start.</li>"#,
+                html_escape::encode_double_quoted_attribute(&vhost::link(state.vhosts.as_deref(), &domain)),
+                html_escape::encode_text(&domain),
+            )),
         }
+    } else {
+        yield Ok(browse::domains(&state.search, state.vhosts.as_deref()));
     }
 
         // Footer and script
@@ -305,38 +813,483 @@ color: var(--color-gray-100);
     Ok(Body::from_stream(stream))
 }
 
-async fn csp(req: Request<Body>, next: Next) -> Response<Body> {
+/// Serves `?version=N` of a page from its history instead of the live file.
+async fn versions(State(state): State<AppState>, req: Request<Body>, next: Next) -> Response<Body> {
+    let path = req.uri().path();
+
+    let version = Query::<HashMap<String, String>>::try_from_uri(req.uri())
+        .ok()
+        .and_then(|Query(params)| params.get("version")?.parse::<u32>().ok());
+
+    let Some(version) = version.filter(|_| path != "/" && !path.starts_with("/_")) else {
+        return next.run(req).await;
+    };
+
+    let (url, mime_type) = match state.store.resolve(path) {
+        Ok(resolved) => resolved,
+        Err(status) => return status.into_response(),
+    };
+
+    match state.store.read_version(&url, version).await {
+        Ok(content) => Response::builder()
+            .header("Content-Type", mime_type.as_ref())
+            .body(Body::from(content))
+            .unwrap(),
+        Err(_) => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+/// Hidden files are never served. They include the temporary files of generations in progress,
+/// which `ServeDir` would hand out half-written and unchecked, and the variants of pages, which
+/// would skip their limits and let anyone guessing a hash read cached form responses.
+async fn hidden(req: Request<Body>, next: Next) -> Response<Body> {
+    if canonical::is_hidden(req.uri().path()) {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+
+    next.run(req).await
+}
+
+/// Sends requests with query parameters selected by `QUERY_PARAMS` past `ServeDir`, which
+/// would serve the page without them.
+async fn variants(State(state): State<AppState>, req: Request<Body>, next: Next) -> Response<Body> {
+    let path = req.uri().path();
+
+    if req.method() != Method::GET
+        || path == "/"
+        || path.starts_with("/_")
+        || matches!(state.variants.select(req.uri().query()), Ok(None))
+    {
+        return next.run(req).await;
+    }
+
+    generate(req.uri().clone(), req.headers().clone(), State(state))
+        .await
+        .into_response()
+}
+
+async fn csp(State(csp): State<HeaderValue>, req: Request<Body>, next: Next) -> Response<Body> {
     let mut response = next.run(req).await;
 
-    let csp = "default-src 'self'; script-src 'self' 'unsafe-inline'; style-src 'self' 'unsafe-inline'; img-src *; font-src 'self'; object-src 'none'; base-uri 'self'; frame-ancestors 'none';";
-    response.headers_mut().insert(
-        "Content-Security-Policy",
-        HeaderValue::from_str(csp).unwrap(),
-    );
+    response
+        .headers_mut()
+        .insert("Content-Security-Policy", csp);
 
     response
 }
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    use dotenvy::EnvLoader;
+/// The config file and environment, overridden by the command line.
+fn config(env: &EnvMap, options: &Options) -> Result<Config, String> {
+    let mut config = Config::load(options.config.as_deref(), env)?;
+
+    if let Some(data_dir) = &options.data_dir {
+        config.data_dir = data_dir.clone();
+    }
+    if let Some(state_dir) = &options.state_dir {
+        config.state_dir = state_dir.clone();
+    }
+    if let Some(backend) = &options.backend {
+        config.backend.kind = backend.parse()?;
+    }
+
+    config.validate()?;
+    Ok(config)
+}
+
+fn store(config: &Config) -> Store {
+    Store::new(
+        &config.data_dir,
+        &config.state_dir,
+        config.partial_output,
+        config.max_path_length,
+    )
+}
+
+/// Everything needed to generate pages, shared by the server and the headless commands. The
+/// search index starts empty, only the server fills it.
+async fn app_state(config: &Config) -> Result<AppState, Box<dyn std::error::Error>> {
+    let backend = config.backend.clone().build()?;
+
+    eprintln!(
+        "using {} backend ({})",
+        backend.name(),
+        backend.model().unwrap_or("default model")
+    );
+
+    let store = Arc::new(store(config));
+
+    Ok(AppState {
+        generations: Arc::new(Generations::new(config.domain_concurrency)),
+        backend,
+        store,
+        search: Arc::default(),
+        events: Arc::new(Events::new(config.event_buffer)),
+        prompts: Arc::new(Prompts::load(
+            &config.prompt_dir,
+            config.vhost_suffix.clone(),
+        )?),
+        profiles: Arc::new(Profiles::new(&config.profiles)),
+        disconnect: config.on_disconnect,
+        context_budget: config.context_budget,
+        asset_limits: assets::Limits {
+            file: config.max_asset_size,
+            total: config.max_total_asset_size,
+        },
+        variants: Arc::new(Variants::new(
+            config.query_params.clone(),
+            config.max_query_length,
+            config.max_form_size,
+            config.max_variants,
+        )),
+        cache_forms: config.cache_form_responses,
+        vhosts: config
+            .vhost_suffix
+            .as_deref()
+            .map(|suffix| Arc::new(VirtualHosts::new(suffix))),
+    })
+}
+
+async fn serve(
+    config: Config,
+    ServeArgs { host }: ServeArgs,
+) -> Result<(), Box<dyn std::error::Error>> {
     use tower_http::services::ServeDir;
 
-    let env = EnvLoader::new().load()?;
-    let gen_map: GenerationMap = Arc::new(Mutex::new(HashMap::new()));
+    let host = host
+        .or(config.host.clone())
+        .ok_or("no address to listen on, set HOST, `host` in the config file or --host")?;
+
+    let mut state = app_state(&config).await?;
 
-    let service = get(generate).with_state(gen_map).into_service();
+    let root = state.store.root().to_path_buf();
+    let search = tokio::task::spawn_blocking(move || SearchIndex::build(root)).await?;
+    eprintln!("indexed {} files", search.len());
+    state.search = Arc::new(search);
 
-    let app = Router::new()
+    let service = get(generate)
+        .post(submit)
+        .with_state(state.clone())
+        .into_service();
+
+    let mut app = Router::new()
         .route("/", get(index))
-        .fallback_service(ServeDir::new("internet").fallback(service))
-        .layer(middleware::from_fn(csp));
+        .route("/_meta/{*path}", get(meta::meta))
+        .route("/_events", get(events::stream))
+        .route("/feed.atom", get(feed::feed))
+        .nest("/_api", api::router());
+
+    // Without a token there is no way to authenticate, so the endpoints don't exist at all
+    if let Some(token) = config.admin_token.clone() {
+        app = app.nest("/_admin", admin::router(token));
+    }
 
-    let listener = tokio::net::TcpListener::bind(env.var("HOST")?)
+    let vhosts = state.vhosts.clone();
+
+    let mut app = app
+        .fallback_service(
+            ServeDir::new(state.store.root())
+                .call_fallback_on_method_not_allowed(true)
+                .fallback(service),
+        )
+        .layer(middleware::from_fn_with_state(state.clone(), variants))
+        .layer(middleware::from_fn_with_state(state.clone(), versions))
+        .layer(middleware::from_fn(hidden))
+        .layer(middleware::from_fn_with_state(config.csp()?, csp))
+        .with_state(state);
+
+    // Layers of a router run after its routing, so the host is looked at by an outer one
+    if let Some(vhosts) = vhosts {
+        app = Router::new()
+            .fallback_service(app)
+            .layer(middleware::from_fn_with_state(vhosts, vhost::route));
+    }
+
+    let listener = tokio::net::TcpListener::bind(&host)
         .await
-        .unwrap();
+        .map_err(|e| format!("cannot listen on {host}: {e}"))?;
 
-    axum::serve(listener, app).await.unwrap();
+    axum::serve(listener, app).await?;
 
     Ok(())
 }
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    use clap::Parser;
+    use dotenvy::EnvLoader;
+
+    let Cli { command, options } = Cli::parse();
+    let env = EnvLoader::new().load()?;
+    let config = config(&env, &options).unwrap_or_else(|e| {
+        // Printed as is, TOML errors point at the offending line
+        eprintln!("{e}");
+        std::process::exit(1);
+    });
+
+    match command.unwrap_or(Command::Serve(ServeArgs::default())) {
+        Command::Serve(args) => serve(config, args).await,
+        Command::Generate(args) => cli::generate(app_state(&config).await?, args).await,
+        Command::Crawl(args) => cli::crawl(app_state(&config).await?, args).await,
+        Command::Prune(args) => cli::prune(&store(&config), args).await,
+        Command::Export(args) => {
+            let root = config.data_dir;
+            tokio::task::spawn_blocking(move || {
+                cli::export(&root, args).map_err(|e| e.to_string())
+            })
+            .await??;
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::{BackendConfig, BackendKind, MockConfig};
+    use crate::store::PartialOutput;
+
+    /// A data and state directory of their own, removed again when dropped.
+    struct Sandbox {
+        dir: PathBuf,
+        state: AppState,
+    }
+
+    impl Sandbox {
+        /// With the mock backend injecting `failure`, as in `MOCK_FAIL`.
+        async fn new(name: &str, failure: Option<&str>, partial: PartialOutput) -> Self {
+            let dir = std::env::temp_dir().join(format!("web2050-{name}-{}", std::process::id()));
+            let _ = std::fs::remove_dir_all(&dir);
+
+            let config = Config {
+                data_dir: dir.join("internet"),
+                state_dir: dir.join("state"),
+                // Missing, so the built-in prompts are used
+                prompt_dir: dir.join("prompts"),
+                partial_output: partial,
+                backend: BackendConfig {
+                    kind: BackendKind::Mock,
+                    mock: MockConfig {
+                        chunk_size: 8,
+                        failure: failure.map(|failure| failure.parse().unwrap()),
+                        fail_after: Some(4),
+                        ..MockConfig::default()
+                    },
+                    ..BackendConfig::default()
+                },
+                ..Config::default()
+            };
+
+            Self {
+                state: app_state(&config).await.unwrap(),
+                dir,
+            }
+        }
+
+        /// Requests `path` and reads the whole response, returning the status or the body, or
+        /// `None` if the body failed.
+        async fn get(&self, path: &str) -> Result<Option<String>, StatusCode> {
+            let response = generate_path(self.state.clone(), path, None, None, None, false).await?;
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX).await;
+            Ok(body
+                .ok()
+                .map(|body| String::from_utf8(body.to_vec()).unwrap()))
+        }
+
+        /// The index page for `params`.
+        async fn index(&self, params: &[(&str, &str)]) -> String {
+            let params = params
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect();
+            let body = index(Query(params), State(self.state.clone()))
+                .await
+                .unwrap();
+            let body = axum::body::to_bytes(body, usize::MAX).await.unwrap();
+            String::from_utf8(body.to_vec()).unwrap()
+        }
+
+        fn files(&self, dir: &str) -> Vec<PathBuf> {
+            jwalk::WalkDir::new(self.dir.join(dir))
+                .skip_hidden(false)
+                .into_iter()
+                .filter_map(Result::ok)
+                .filter(|entry| entry.file_type().is_file())
+                .map(|entry| entry.path())
+                .collect()
+        }
+    }
+
+    impl Drop for Sandbox {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    #[tokio::test]
+    async fn a_clean_run_is_committed() {
+        let sandbox = Sandbox::new("clean", None, PartialOutput::Discard).await;
+
+        let body = sandbox
+            .get("/example.com/index.html")
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(body.contains("<h1>example.com/index.html</h1>"), "{body}");
+
+        // Streamed as the model wrote it, stored after the profile's post-processing
+        let stored = std::fs::read_to_string(sandbox.dir.join("internet/example.com/index.html"));
+        assert_eq!(stored.unwrap(), body.trim());
+        assert_eq!(
+            sandbox
+                .state
+                .store
+                .versions("example.com/index.html")
+                .await
+                .unwrap()
+                .len(),
+            1
+        );
+
+        // Served from the store afterwards instead of being generated again
+        assert_eq!(
+            sandbox
+                .get("/example.com/index.html")
+                .await
+                .unwrap()
+                .unwrap(),
+            body.trim()
+        );
+    }
+
+    #[tokio::test]
+    async fn failed_runs_commit_nothing() {
+        for failure in ["connect", "midstream", "truncate", "error"] {
+            let sandbox = Sandbox::new(failure, Some(failure), PartialOutput::Discard).await;
+
+            let response = sandbox.get("/example.com/index.html").await;
+            match failure {
+                "connect" => assert_eq!(response, Err(StatusCode::BAD_GATEWAY)),
+                _ => assert_eq!(response, Ok(None), "{failure}"),
+            }
+
+            // Neither the page nor its temporary file is left behind
+            assert_eq!(
+                sandbox.files("internet"),
+                Vec::<PathBuf>::new(),
+                "{failure}"
+            );
+            assert_eq!(sandbox.files("state"), Vec::<PathBuf>::new(), "{failure}");
+        }
+    }
+
+    #[tokio::test]
+    async fn failed_runs_are_quarantined() {
+        for failure in ["midstream", "truncate", "error"] {
+            let name = format!("quarantine-{failure}");
+            let sandbox = Sandbox::new(&name, Some(failure), PartialOutput::Quarantine).await;
+
+            assert_eq!(
+                sandbox.get("/example.com/index.html").await,
+                Ok(None),
+                "{name}"
+            );
+            assert_eq!(sandbox.files("internet"), Vec::<PathBuf>::new(), "{name}");
+
+            let quarantined = sandbox.files("state/quarantine");
+            assert_eq!(quarantined.len(), 1, "{name}");
+            assert!(
+                quarantined[0]
+                    .to_string_lossy()
+                    .contains("example.com/index.html."),
+                "{name}: {quarantined:?}"
+            );
+            assert!(sandbox.files("state/history").is_empty(), "{name}");
+        }
+    }
+
+    /// Whether `needle` appears within a tag rather than in text, where quotes are harmless.
+    fn in_tags(html: &str, needle: &str) -> bool {
+        html.split('<')
+            .any(|tag| tag.split('>').next().unwrap_or_default().contains(needle))
+    }
+
+    #[tokio::test]
+    async fn index_escapes_domains_in_links() {
+        let sandbox = Sandbox::new("escape", None, PartialOutput::Discard).await;
+
+        let html = sandbox
+            .index(&[("domain", r#"x"onmouseover="alert(1)"#)])
+            .await;
+        assert!(!in_tags(&html, r#"x"onmouseover"#), "{html}");
+        assert!(html.contains(r#"href="/x&quot;onmouseover=&quot;alert(1)""#));
+
+        // Directory names may contain quotes too
+        let now = std::time::SystemTime::now();
+        sandbox
+            .state
+            .search
+            .update(r#"x"onclick="y.com/index.html"#, "<p>hello</p>", now);
+
+        let html = sandbox.index(&[]).await;
+        assert!(!in_tags(&html, r#"x"onclick"#), "{html}");
+
+        let html = sandbox.index(&[("q", "hello")]).await;
+        assert!(!in_tags(&html, r#"x"onclick"#), "{html}");
+    }
+
+    #[tokio::test]
+    async fn index_links_to_virtual_hosts() {
+        let mut sandbox = Sandbox::new("index-vhosts", None, PartialOutput::Discard).await;
+        sandbox.state.vhosts = Some(Arc::new(VirtualHosts::new("web2050.local")));
+
+        let now = std::time::SystemTime::now();
+        sandbox
+            .state
+            .search
+            .update("example.com/docs/about.html", "<p>hello</p>", now);
+
+        let page = r#"href="//example.com.web2050.local/docs/about.html""#;
+        assert!(sandbox.index(&[("q", "hello")]).await.contains(page));
+        assert!(
+            sandbox
+                .index(&[("domain", "example.com")])
+                .await
+                .contains(page)
+        );
+
+        let html = sandbox.index(&[]).await;
+        assert!(
+            html.contains(r#"href="//example.com.web2050.local/">visit</a>"#),
+            "{html}"
+        );
+        assert!(html.contains(r#"data-suffix=".web2050.local""#));
+
+        // Browsing stays on the main host
+        assert!(html.contains(r#"href="/?domain=example.com""#));
+    }
+
+    #[tokio::test]
+    async fn joiners_get_a_file_committed_before_the_owner_looked() {
+        let sandbox = Sandbox::new("committed", None, PartialOutput::Discard).await;
+        let url = Path::new("example.com/index.html");
+        let path = sandbox.state.store.path(url);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, "<p>done</p>").unwrap();
+
+        let Claim::Owner(in_flight) = sandbox.state.generations.claim(url) else {
+            panic!("nobody was generating it");
+        };
+        let Claim::Joiner(hub) = sandbox.state.generations.claim(url) else {
+            panic!("it is being generated");
+        };
+        let joined = stream_subscription(hub.subscribe(), &mime_guess::mime::TEXT_HTML);
+
+        let response = serve_existing(&path, &mime_guess::mime::TEXT_HTML, &in_flight).await;
+        drop(in_flight);
+
+        for response in [response.unwrap(), joined] {
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX).await;
+            assert_eq!(body.unwrap(), "<p>done</p>");
+        }
+    }
+}
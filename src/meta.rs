@@ -0,0 +1,64 @@
+//! Provenance of generated files: which model and prompt produced them, when, and at what cost.
+//!
+//! A record is written next to every version in the history directory and served as JSON at
+//! `/_meta/<path>`, optionally for an older `?version=N`.
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
+use axum::response::Json;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::AppState;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Metadata {
+    pub path: String,
+    /// The selected query parameters a variant was generated for, see `variants.rs`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub query: Option<String>,
+    /// Filled in by the store on commit.
+    pub version: u32,
+    pub backend: String,
+    pub model: Option<String>,
+    pub prompt_version: String,
+    /// Generation profile, see `profile.rs`. Empty for files generated before profiles existed.
+    #[serde(default)]
+    pub profile: String,
+    /// RFC 3339
+    pub created: String,
+    pub prompt_tokens: Option<u64>,
+    pub completion_tokens: Option<u64>,
+    /// From sending the request to the end of the stream.
+    pub latency_ms: u64,
+    pub first_token_ms: Option<u64>,
+    pub finish_reason: String,
+    /// Files of the domain that were given to the model as context.
+    pub assets: Vec<String>,
+    /// Files of the domain that could not be read, and why.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub skipped_assets: Vec<String>,
+}
+
+pub async fn meta(
+    Path(path): Path<String>,
+    Query(params): Query<HashMap<String, String>>,
+    State(state): State<AppState>,
+) -> Result<Json<Metadata>, StatusCode> {
+    let (url, _) = state.store.resolve(&path)?;
+
+    let version = match params.get("version") {
+        Some(version) => version.parse().map_err(|_| StatusCode::BAD_REQUEST)?,
+        None => state
+            .store
+            .current_version(&url)
+            .await
+            .ok_or(StatusCode::NOT_FOUND)?,
+    };
+
+    state
+        .store
+        .metadata(&url, version)
+        .await
+        .map(Json)
+        .ok_or(StatusCode::NOT_FOUND)
+}
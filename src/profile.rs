@@ -0,0 +1,904 @@
+//! Generation profiles per file type.
+//!
+//! A profile picks extra prompt instructions, the model and its sampling settings, and the
+//! post-processors the finished file goes through before it is saved. Profiles are matched by
+//! extension first, then by MIME type. The built-in ones below can be adjusted, and new ones
+//! added, under `[profiles.<name>]` in the config file.
+use mime_guess::Mime;
+use serde::{Deserialize, Deserializer};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::backend::RequestOptions;
+
+const BUILTIN: &str = r#"
+[html]
+extensions = ["html", "htm"]
+post = ["trim"]
+
+[css]
+extensions = ["css"]
+instructions = "Write only CSS. Refer to other files by absolute paths on the same domain, never `@import` or `url()` anything from another host."
+post = ["trim"]
+
+[js]
+extensions = ["js", "mjs"]
+instructions = "Write only JavaScript that works entirely offline. It must not use fetch, XMLHttpRequest, WebSocket, EventSource, navigator.sendBeacon, importScripts or import(); keep any data it needs inline."
+post = ["trim", "no-network"]
+
+[svg]
+extensions = ["svg"]
+instructions = "Write a single standalone SVG document whose root is an `<svg>` element with the SVG namespace. Do not use scripts, event handler attributes, `<foreignObject>` or references to other hosts."
+post = ["trim", "strict-svg"]
+
+[json]
+extensions = ["json"]
+instructions = "Write only valid JSON, without comments or trailing commas."
+post = ["trim", "json"]
+
+[xml]
+extensions = ["xml", "rss", "atom"]
+instructions = "Write only well-formed XML, starting with the XML declaration."
+post = ["trim"]
+
+[csv]
+extensions = ["csv", "tsv"]
+instructions = "Write only CSV, or tab-separated values for `.tsv` files, with a header row."
+post = ["trim"]
+
+[text]
+extensions = ["txt", "md", "markdown"]
+mime = ["text/plain"]
+instructions = "Write plain text, or Markdown for `.md` files, without any HTML."
+post = ["trim"]
+"#;
+
+/// Used when no profile matches.
+const DEFAULT: &str = "default";
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Profile {
+    #[serde(skip)]
+    pub name: String,
+    /// Extensions, without the dot, the profile applies to.
+    pub extensions: Vec<String>,
+    /// MIME types it applies to when no profile claims the extension. `text/*` matches every
+    /// text type.
+    pub mime: Vec<String>,
+    /// Available to the prompt templates as `instructions`.
+    pub instructions: Option<String>,
+    pub model: Option<String>,
+    pub temperature: Option<f32>,
+    pub max_tokens: Option<u32>,
+    /// Run in order on the finished file.
+    pub post: Option<Vec<PostProcessor>>,
+}
+
+/// Checks, and possibly rewrites, a finished file before it is saved. A file that fails a check
+/// is treated like any other failed generation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PostProcessor {
+    /// Strips leading and trailing whitespace, which for one breaks an `<?xml` declaration.
+    Trim,
+    /// A standalone SVG without scripts, event handlers, `<foreignObject>` or external
+    /// references.
+    StrictSvg,
+    /// JavaScript without network APIs, `import` or `eval`.
+    NoNetwork,
+    /// Valid JSON.
+    Json,
+}
+
+pub struct Profiles {
+    profiles: Vec<Profile>,
+    default: Profile,
+}
+
+impl Profiles {
+    /// The built-in profiles, adjusted and extended by `configured`.
+    pub fn new(configured: &BTreeMap<String, Profile>) -> Self {
+        let builtin: BTreeMap<String, Profile> =
+            toml::from_str(BUILTIN).expect("built-in profiles are valid");
+
+        // New profiles go first, so they can claim extensions of the built-in ones
+        let mut profiles: Vec<Profile> = configured
+            .iter()
+            .filter(|(name, _)| !builtin.contains_key(*name))
+            .map(|(name, profile)| profile.clone().named(name))
+            .collect();
+
+        for (name, profile) in builtin {
+            let profile = match configured.get(&name) {
+                Some(overrides) => profile.merge(overrides.clone()),
+                None => profile,
+            };
+            profiles.push(profile.named(&name));
+        }
+
+        Self {
+            profiles,
+            default: Profile::default().named(DEFAULT),
+        }
+    }
+
+    /// The profile for generating `path`.
+    pub fn select(&self, path: &Path, mime: &Mime) -> &Profile {
+        let extension = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .unwrap_or_default();
+
+        let by_extension = self.profiles.iter().find(|profile| {
+            profile
+                .extensions
+                .iter()
+                .any(|e| e.eq_ignore_ascii_case(extension))
+        });
+
+        by_extension
+            .or_else(|| {
+                self.profiles.iter().find(|profile| {
+                    profile
+                        .mime
+                        .iter()
+                        .any(|pattern| match pattern.split_once("/*") {
+                            Some((kind, "")) => mime.type_() == kind,
+                            _ => mime.essence_str() == pattern,
+                        })
+                })
+            })
+            .unwrap_or(&self.default)
+    }
+}
+
+impl Profile {
+    fn named(mut self, name: &str) -> Self {
+        self.name = name.to_string();
+        self
+    }
+
+    /// `self` with every setting `overrides` makes replaced.
+    fn merge(self, overrides: Profile) -> Self {
+        let or = |list: Vec<String>, other: Vec<String>| if list.is_empty() { other } else { list };
+
+        Self {
+            name: self.name,
+            extensions: or(overrides.extensions, self.extensions),
+            mime: or(overrides.mime, self.mime),
+            instructions: overrides.instructions.or(self.instructions),
+            model: overrides.model.or(self.model),
+            temperature: overrides.temperature.or(self.temperature),
+            max_tokens: overrides.max_tokens.or(self.max_tokens),
+            post: overrides.post.or(self.post),
+        }
+    }
+
+    pub fn validate(&self, name: &str) -> Result<(), String> {
+        if let Some(temperature) = self.temperature
+            && !(temperature.is_finite() && temperature >= 0.0)
+        {
+            return Err(format!("profiles.{name}.temperature must not be negative"));
+        }
+
+        if self.max_tokens == Some(0) {
+            return Err(format!("profiles.{name}.max_tokens must be at least 1"));
+        }
+
+        if let Some(pattern) = self.mime.iter().find(|pattern| !pattern.contains('/')) {
+            return Err(format!(
+                "profiles.{name}.mime `{pattern}` is not a MIME type, e.g. `text/plain` or `text/*`"
+            ));
+        }
+
+        Ok(())
+    }
+
+    pub fn request_options(&self) -> RequestOptions {
+        RequestOptions {
+            model: self.model.clone(),
+            temperature: self.temperature,
+            max_tokens: self.max_tokens,
+        }
+    }
+
+    /// Whether a post-processor checks the output, which then only reaches clients once it
+    /// passed.
+    pub fn checks(&self) -> bool {
+        self.post
+            .iter()
+            .flatten()
+            .any(|processor| *processor != PostProcessor::Trim)
+    }
+
+    /// Runs the post-processors, returning the content to save or why it must not be saved.
+    pub fn post_process(&self, mut content: String) -> Result<String, String> {
+        for processor in self.post.iter().flatten() {
+            content = processor
+                .apply(content)
+                .map_err(|e| format!("{processor}: {e}"))?;
+        }
+        Ok(content)
+    }
+}
+
+impl PostProcessor {
+    fn apply(self, content: String) -> Result<String, String> {
+        match self {
+            Self::Trim => Ok(content.trim().to_string()),
+            Self::StrictSvg => strict_svg(&content).map(|_| content),
+            Self::NoNetwork => no_network(&content).map(|_| content),
+            Self::Json => serde_json::from_str::<serde_json::Value>(&content)
+                .map(|_| content)
+                .map_err(|e| format!("invalid JSON: {e}")),
+        }
+    }
+}
+
+/// Scans the markup, so text content and comments can mention anything.
+fn strict_svg(content: &str) -> Result<(), String> {
+    const NOT_STANDALONE: &str = "not a standalone SVG document";
+
+    let svg = content.trim();
+    let mut depth = 0usize;
+    let mut root = false;
+    let mut style = false;
+    let mut at = 0;
+
+    while at < svg.len() {
+        let start = svg[at..].find('<').map_or(svg.len(), |start| at + start);
+        let text = &svg[at..start];
+
+        if depth == 0 && !text.trim().is_empty() {
+            return Err(NOT_STANDALONE.into());
+        }
+        if style {
+            svg_css(text)?;
+        }
+        if start == svg.len() {
+            break;
+        }
+
+        let rest = &svg[start..];
+        let (end, inner) = if let Some(comment) = rest.strip_prefix("<!--") {
+            let end = comment.find("-->").ok_or("has an unterminated comment")?;
+            (start + "<!--".len() + end + "-->".len(), None)
+        } else if let Some(cdata) = rest.strip_prefix("<![CDATA[") {
+            let end = cdata
+                .find("]]>")
+                .ok_or("has an unterminated CDATA section")?;
+            (
+                start + "<![CDATA[".len() + end + "]]>".len(),
+                Some(&cdata[..end]),
+            )
+        } else if rest.starts_with("<?") || rest.starts_with("<!") {
+            // The XML declaration and doctype, which only come before the root
+            if root {
+                return Err(NOT_STANDALONE.into());
+            }
+            let end = rest.find('>').ok_or(NOT_STANDALONE)?;
+            (start + end + 1, None)
+        } else if !rest[1..].starts_with(|c: char| c.is_ascii_alphabetic() || c == '/') {
+            return Err("has a stray `<`".into());
+        } else {
+            let end = start + tag_end(rest).ok_or("has an unterminated tag")?;
+            let tag = &svg[start..end];
+            let name = tag_name(tag);
+
+            if let Some(closing) = tag.strip_prefix("</") {
+                depth = depth.checked_sub(1).ok_or(NOT_STANDALONE)?;
+                if tag_name(&format!("<{closing}")) == "style" {
+                    style = false;
+                }
+            } else {
+                if depth == 0 && (root || name != "svg") {
+                    return Err(NOT_STANDALONE.into());
+                }
+                root = true;
+
+                if let Some(forbidden) = ["script", "foreignobject"].iter().find(|f| **f == name) {
+                    return Err(format!("contains `<{forbidden}`"));
+                }
+                svg_attributes(tag)?;
+
+                if !tag.ends_with("/>") {
+                    depth += 1;
+                    style = name == "style";
+                }
+            }
+
+            (end, None)
+        };
+
+        if let Some(inner) = inner
+            && style
+        {
+            svg_css(inner)?;
+        }
+        at = end;
+    }
+
+    if !root || depth != 0 {
+        return Err(NOT_STANDALONE.into());
+    }
+
+    Ok(())
+}
+
+fn svg_attributes(tag: &str) -> Result<(), String> {
+    for (name, value) in attributes(tag) {
+        if name.starts_with("on") {
+            return Err(format!("has an event handler, `{name}`"));
+        }
+
+        let compact: String = value
+            .chars()
+            .filter(|c| !c.is_ascii_whitespace())
+            .collect::<String>()
+            .to_ascii_lowercase();
+        if compact.contains("javascript:") {
+            return Err("contains `javascript:`".into());
+        }
+
+        if (name == "src" || name.ends_with("href")) && external(value) {
+            return Err("references another host".into());
+        }
+        if name == "style" || compact.contains("url(") {
+            svg_css(value)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Checks the CSS of `<style>` elements and attributes.
+fn svg_css(css: &str) -> Result<(), String> {
+    let lower = css.to_ascii_lowercase();
+
+    if lower.contains("@import") {
+        return Err("contains `@import`".into());
+    }
+
+    for (i, _) in lower.match_indices("url(") {
+        let target = lower[i + "url(".len()..]
+            .split(')')
+            .next()
+            .unwrap_or_default();
+        if external(target.trim().trim_matches(['"', '\''])) {
+            return Err("references another host".into());
+        }
+    }
+
+    Ok(())
+}
+
+/// Whether `url` points at another host.
+fn external(url: &str) -> bool {
+    let url = url.trim();
+    url.starts_with("//") || url.contains("://")
+}
+
+/// The lowercase name of the element `tag` opens or closes.
+fn tag_name(tag: &str) -> String {
+    tag.trim_start_matches(['<', '/'])
+        .split(|c: char| c.is_ascii_whitespace() || c == '/' || c == '>')
+        .next()
+        .unwrap_or_default()
+        .to_ascii_lowercase()
+}
+
+/// The attributes of a complete tag, as their lowercase name and value.
+fn attributes(tag: &str) -> Vec<(String, &str)> {
+    let mut attributes = Vec::new();
+    let mut rest = tag
+        .trim_start_matches('<')
+        .trim_end_matches('>')
+        .trim_end_matches('/');
+    rest = rest.trim_start_matches(|c: char| !c.is_ascii_whitespace());
+
+    loop {
+        rest = rest.trim_start_matches(|c: char| c.is_ascii_whitespace() || c == '/');
+        if rest.is_empty() {
+            break;
+        }
+
+        let name_end = rest
+            .find(|c: char| c == '=' || c.is_ascii_whitespace())
+            .unwrap_or(rest.len());
+        let name = rest[..name_end].to_ascii_lowercase();
+        rest = rest[name_end..].trim_start();
+
+        let Some(value) = rest.strip_prefix('=') else {
+            attributes.push((name, ""));
+            continue;
+        };
+        let value = value.trim_start();
+
+        let (value, after) = match value.chars().next() {
+            Some(quote @ ('"' | '\'')) => {
+                let end = value[1..].find(quote).map_or(value.len(), |end| end + 1);
+                (&value[1..end], value.get(end + 1..).unwrap_or_default())
+            }
+            _ => {
+                let end = value
+                    .find(|c: char| c.is_ascii_whitespace())
+                    .unwrap_or(value.len());
+                value.split_at(end)
+            }
+        };
+
+        attributes.push((name, value));
+        rest = after;
+    }
+
+    attributes
+}
+
+/// The length of the tag `markup` starts with, with `>` in quoted attribute values skipped.
+fn tag_end(markup: &str) -> Option<usize> {
+    let mut quote = None;
+
+    for (i, c) in markup.char_indices() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '>') => return Some(i + 1),
+            _ => {}
+        }
+    }
+
+    None
+}
+
+/// Scans the code only, so comments and strings can mention the APIs. Strings are still looked
+/// at whole, since `window["fetch"]` is as good as `fetch`, and computed access to the global
+/// object is refused, since it could assemble any name. The CSP blocks other hosts anyway, this
+/// keeps a page from reaching the server behind its back.
+fn no_network(content: &str) -> Result<(), String> {
+    const APIS: [&str; 10] = [
+        "fetch",
+        "XMLHttpRequest",
+        "WebSocket",
+        "EventSource",
+        "sendBeacon",
+        "importScripts",
+        "RTCPeerConnection",
+        "eval",
+        "Function",
+        "import",
+    ];
+    const GLOBALS: [&str; 6] = ["window", "self", "globalThis", "top", "parent", "frames"];
+
+    let (code, strings) = strip_literals(content);
+
+    if let Some(api) = strings
+        .iter()
+        .find_map(|string| APIS.iter().find(|api| string == *api))
+    {
+        return Err(format!("uses `{api}`"));
+    }
+
+    for (start, end) in identifiers(&code) {
+        let identifier = &code[start..end];
+        let before = code[..start].trim_end();
+        let after = code[end..].trim_start();
+
+        // `x.import`, not spread like `...import`
+        let property = before.ends_with('.') && !before.ends_with("...");
+
+        match identifier {
+            // `import.meta` is the only use of `import` that loads nothing
+            "import" if property || after.starts_with('.') => {}
+            api if APIS.contains(&api) => return Err(format!("uses `{api}`")),
+            global if GLOBALS.contains(&global) && !property && after.starts_with('[') => {
+                return Err(format!("uses `{global}[...]`"));
+            }
+            // `export { x } from "./x.js"` loads a module like `import` does
+            "export" if !property => {
+                let statement = after.split([';', '\n']).next().unwrap_or_default();
+                let from = identifiers(statement).any(|(s, e)| {
+                    &statement[s..e] == "from"
+                        && !statement[..s].ends_with('.')
+                        && statement[e..].trim_start().starts_with(['"', '\''])
+                });
+                if from {
+                    return Err("uses `export ... from`".into());
+                }
+            }
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+/// The spans of the identifiers and keywords in `code`.
+fn identifiers(code: &str) -> impl Iterator<Item = (usize, usize)> + '_ {
+    let identifier = |c: char| c.is_alphanumeric() || c == '_' || c == '$';
+    let mut at = 0;
+
+    std::iter::from_fn(move || {
+        loop {
+            let start = at + code[at..].find(identifier)?;
+            let end = code[start..]
+                .find(|c: char| !identifier(c))
+                .map_or(code.len(), |end| start + end);
+            at = end;
+
+            // Numbers like `1e5` are not identifiers
+            if !code[start..].starts_with(|c: char| c.is_ascii_digit()) {
+                return Some((start, end));
+            }
+        }
+    })
+}
+
+/// `js` without comments and with the contents of string and template literals removed, and
+/// those contents. Expressions in templates stay code.
+fn strip_literals(js: &str) -> (String, Vec<String>) {
+    #[derive(Clone, Copy, PartialEq)]
+    enum State {
+        Code,
+        LineComment,
+        BlockComment,
+        String(char),
+        Template,
+    }
+
+    let mut code = String::with_capacity(js.len());
+    let mut strings = Vec::new();
+    let mut literal = String::new();
+    // Open braces of every `${` expression we are in
+    let mut expressions: Vec<usize> = Vec::new();
+    let mut state = State::Code;
+    let mut chars = js.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match state {
+            State::Code => match c {
+                '/' if chars.peek() == Some(&'/') => state = State::LineComment,
+                '/' if chars.peek() == Some(&'*') => {
+                    chars.next();
+                    state = State::BlockComment;
+                    code.push(' ');
+                }
+                '"' | '\'' => {
+                    state = State::String(c);
+                    code.push(c);
+                }
+                '`' => {
+                    state = State::Template;
+                    code.push(c);
+                }
+                '{' => {
+                    if let Some(depth) = expressions.last_mut() {
+                        *depth += 1;
+                    }
+                    code.push(c);
+                }
+                '}' if expressions.last() == Some(&0) => {
+                    expressions.pop();
+                    state = State::Template;
+                    code.push(c);
+                }
+                '}' => {
+                    if let Some(depth) = expressions.last_mut() {
+                        *depth -= 1;
+                    }
+                    code.push(c);
+                }
+                c => code.push(c),
+            },
+            State::LineComment => {
+                if c == '\n' {
+                    state = State::Code;
+                    code.push(c);
+                }
+            }
+            State::BlockComment => {
+                if c == '*' && chars.peek() == Some(&'/') {
+                    chars.next();
+                    state = State::Code;
+                }
+            }
+            State::String(quote) => match c {
+                '\\' => {
+                    if let Some(escaped) = chars.next() {
+                        literal.push(escaped);
+                    }
+                }
+                // Unterminated, the line ends it
+                c if c == quote || c == '\n' => {
+                    strings.push(std::mem::take(&mut literal));
+                    state = State::Code;
+                    code.push(c);
+                }
+                c => literal.push(c),
+            },
+            State::Template => match c {
+                '\\' => {
+                    if let Some(escaped) = chars.next() {
+                        literal.push(escaped);
+                    }
+                }
+                '`' => {
+                    strings.push(std::mem::take(&mut literal));
+                    state = State::Code;
+                    code.push(c);
+                }
+                '$' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    strings.push(std::mem::take(&mut literal));
+                    expressions.push(0);
+                    state = State::Code;
+                    code.push_str("${");
+                }
+                c => literal.push(c),
+            },
+        }
+    }
+
+    if !literal.is_empty() {
+        strings.push(literal);
+    }
+
+    (code, strings)
+}
+
+impl FromStr for PostProcessor {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, String> {
+        Ok(match name {
+            "trim" => Self::Trim,
+            "strict-svg" => Self::StrictSvg,
+            "no-network" => Self::NoNetwork,
+            "json" => Self::Json,
+            other => {
+                return Err(format!(
+                    "unknown post-processor `{other}`, expected one of: trim, strict-svg, no-network, json"
+                ));
+            }
+        })
+    }
+}
+
+impl fmt::Display for PostProcessor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Trim => "trim",
+            Self::StrictSvg => "strict-svg",
+            Self::NoNetwork => "no-network",
+            Self::Json => "json",
+        })
+    }
+}
+
+impl<'de> Deserialize<'de> for PostProcessor {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        crate::config::parse(deserializer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn svg(content: &str) -> Result<(), String> {
+        strict_svg(content)
+    }
+
+    #[test]
+    fn trim() {
+        assert_eq!(
+            PostProcessor::Trim.apply("\n  <?xml version=\"1.0\"?><svg/>\n\n".into()),
+            Ok("<?xml version=\"1.0\"?><svg/>".into())
+        );
+    }
+
+    #[test]
+    fn json() {
+        assert_eq!(
+            PostProcessor::Json.apply(r#"{"a": [1, 2]}"#.into()),
+            Ok(r#"{"a": [1, 2]}"#.into())
+        );
+        for invalid in [r#"{"a": 1,}"#, "// comment\n{}", "{'a': 1}", ""] {
+            assert!(
+                PostProcessor::Json.apply(invalid.into()).is_err(),
+                "{invalid}"
+            );
+        }
+    }
+
+    #[test]
+    fn strict_svg_accepts_plain_documents() {
+        let documents = [
+            r#"<svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 1 1"><rect width="1" height="1"/></svg>"#,
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE svg PUBLIC "-//W3C//DTD SVG 1.1//EN" "http://www.w3.org/Graphics/SVG/1.1/DTD/svg11.dtd">
+<svg xmlns="http://www.w3.org/2000/svg"><circle r="1"/></svg>"#,
+            "<!-- A logo --><svg xmlns=\"http://www.w3.org/2000/svg\"></svg><!-- end -->",
+            // Text and comments may say anything
+            r#"<svg xmlns="http://www.w3.org/2000/svg"><text x="0"> once = 1, see https://example.com</text><!-- <script> onload= --></svg>"#,
+            r##"<svg xmlns="http://www.w3.org/2000/svg" xmlns:xlink="http://www.w3.org/1999/xlink"><defs><linearGradient id="g"/></defs><rect fill="url(#g)"/><use xlink:href="#g"/><a href="/example.com/about.html"><text>About</text></a></svg>"##,
+            r#"<svg xmlns="http://www.w3.org/2000/svg"><style>rect { fill: url(#g); } /* a > b */</style><rect style="fill: red" data-label="x > y"/></svg>"#,
+            r#"<svg xmlns="http://www.w3.org/2000/svg"><image href="data:image/png;base64,AAAA"/></svg>"#,
+            r#"<SVG xmlns="http://www.w3.org/2000/svg"><g><g/></g></SVG>"#,
+        ];
+
+        for document in documents {
+            assert_eq!(svg(document), Ok(()), "{document}");
+        }
+    }
+
+    #[test]
+    fn strict_svg_rejects_active_content() {
+        let documents = [
+            ("<svg><script>alert(1)</script></svg>", "contains `<script`"),
+            ("<svg><SCRIPT href=\"/x.js\"/></svg>", "contains `<script`"),
+            (
+                "<svg><foreignObject><div/></foreignObject></svg>",
+                "contains `<foreignobject`",
+            ),
+            (
+                "<svg onload=\"alert(1)\"></svg>",
+                "has an event handler, `onload`",
+            ),
+            (
+                "<svg><rect\nONCLICK = 'x()'/></svg>",
+                "has an event handler, `onclick`",
+            ),
+            (
+                "<svg><a href=\"java\tscript:alert(1)\"/></svg>",
+                "contains `javascript:`",
+            ),
+            (
+                "<svg><style>@import url(/x.css);</style></svg>",
+                "contains `@import`",
+            ),
+            (
+                "<svg><style><![CDATA[ @import 'x.css'; ]]></style></svg>",
+                "contains `@import`",
+            ),
+        ];
+
+        for (document, reason) in documents {
+            assert_eq!(svg(document), Err(reason.to_string()), "{document}");
+        }
+    }
+
+    #[test]
+    fn strict_svg_rejects_other_hosts() {
+        for document in [
+            r#"<svg><image href="https://example.com/a.png"/></svg>"#,
+            r#"<svg><use xlink:href="//example.com/a.svg#x"/></svg>"#,
+            r#"<svg><rect fill="url('https://example.com/p.svg#p')"/></svg>"#,
+            r#"<svg><rect style="fill: url(//example.com/p.svg#p)"/></svg>"#,
+            r#"<svg><style>rect { fill: url("http://example.com/p.svg#p") }</style></svg>"#,
+        ] {
+            assert_eq!(
+                svg(document),
+                Err("references another host".to_string()),
+                "{document}"
+            );
+        }
+    }
+
+    #[test]
+    fn strict_svg_rejects_other_documents() {
+        for document in [
+            "",
+            "<html><svg></svg></html>",
+            "Here is your SVG: <svg></svg>",
+            "<svg></svg> Hope this helps!",
+            "<svg></svg><svg></svg>",
+            "<svg><g></svg>",
+            "<svg></g></svg></svg>",
+            "<svg><rect",
+            "<svg><!-- unterminated </svg>",
+            "<svg></svg><?xml version=\"1.0\"?>",
+        ] {
+            assert!(svg(document).is_err(), "{document}");
+        }
+    }
+
+    #[test]
+    fn no_network_ignores_comments_and_strings() {
+        for script in [
+            "// we never fetch anything\nconst x = 1;",
+            "/* no XMLHttpRequest, no WebSocket */ let y = 2;",
+            "const label = 'WebSocket support';",
+            "const help = \"use fetch() to load data\";",
+            "const t = `import ${name} from somewhere`;",
+            "const prefetched = prefetch + fetcher;",
+            "console.log(import.meta.url);",
+            "export function main() { return Array.from(items); }",
+            "export default { from: 1 };",
+            "const n = 1e5;",
+        ] {
+            assert_eq!(no_network(script), Ok(()), "{script}");
+        }
+    }
+
+    #[test]
+    fn no_network_rejects_network_apis() {
+        let scripts = [
+            ("fetch('/api')", "uses `fetch`"),
+            ("window.fetch('/api')", "uses `fetch`"),
+            ("const r = new XMLHttpRequest();", "uses `XMLHttpRequest`"),
+            ("new WebSocket(url)", "uses `WebSocket`"),
+            ("navigator.sendBeacon('/log', data)", "uses `sendBeacon`"),
+            ("const m = await import('./m.js');", "uses `import`"),
+            ("import x from './x.js';", "uses `import`"),
+            ("import { a } from \"./a.js\"", "uses `import`"),
+            ("import './side-effect.js';", "uses `import`"),
+            ("export { a } from './a.js';", "uses `export ... from`"),
+            ("export * from \"./all.js\"", "uses `export ... from`"),
+            ("eval(code)", "uses `eval`"),
+            ("new Function('return 1')", "uses `Function`"),
+            ("const t = `${fetch('/x')}`;", "uses `fetch`"),
+            ("window['fetch']('/x')", "uses `fetch`"),
+            ("self[\"WebSocket\"]", "uses `WebSocket`"),
+            ("window['fet' + 'ch']('/x')", "uses `window[...]`"),
+            ("globalThis[name]()", "uses `globalThis[...]`"),
+        ];
+
+        for (script, reason) in scripts {
+            assert_eq!(no_network(script), Err(reason.to_string()), "{script}");
+        }
+    }
+
+    #[test]
+    fn string_literals_are_separated_from_code() {
+        let (code, strings) = strip_literals("a('b\\'c', \"d\") // e\n/* f */ `g${h + `i`}j` + k");
+        assert_eq!(code, "a('', \"\") \n  `${h + ``}` + k");
+        assert_eq!(strings, ["b'c", "d", "g", "i", "j"]);
+    }
+
+    #[test]
+    fn profiles_are_selected_by_extension_then_mime() {
+        let profiles = Profiles::new(&BTreeMap::new());
+        let select = |path: &str| {
+            let mime = mime_guess::from_path(path).first_or(mime_guess::mime::TEXT_HTML);
+            profiles.select(Path::new(path), &mime).name.clone()
+        };
+
+        assert_eq!(select("example.com/index.html"), "html");
+        assert_eq!(select("example.com/style.css"), "css");
+        assert_eq!(select("example.com/app.mjs"), "js");
+        assert_eq!(select("example.com/logo.svg"), "svg");
+        assert_eq!(select("example.com/data.json"), "json");
+        assert_eq!(select("example.com/feed.xml"), "xml");
+        assert_eq!(select("example.com/sitemap.rss"), "xml");
+        assert_eq!(select("example.com/table.csv"), "csv");
+        assert_eq!(select("example.com/robots.txt"), "text");
+        assert_eq!(select("example.com/README.md"), "text");
+        assert_eq!(select("example.com/notes.text"), "text");
+        // Other text types get no instructions rather than the plain text ones
+        assert_eq!(select("example.com/event.ics"), DEFAULT);
+    }
+
+    #[test]
+    fn post_processors_run_in_order() {
+        let profile = Profile {
+            post: Some(vec![PostProcessor::Trim, PostProcessor::Json]),
+            ..Profile::default()
+        };
+        assert!(profile.checks());
+        assert_eq!(profile.post_process(" {} \n".into()), Ok("{}".into()));
+        assert_eq!(
+            profile.post_process("{".into()),
+            Err("json: invalid JSON: EOF while parsing an object at line 1 column 1".into())
+        );
+
+        let trim = Profile {
+            post: Some(vec![PostProcessor::Trim]),
+            ..Profile::default()
+        };
+        assert!(!trim.checks());
+        assert!(!Profile::default().checks());
+    }
+}
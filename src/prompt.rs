@@ -0,0 +1,352 @@
+//! System and user prompts, rendered from the minijinja templates `system.jinja` and
+//! `user.jinja` in `PROMPT_DIR`. A template missing there falls back to the built-in copy.
+//!
+//! The templates are checked for changes before every generation, so prompts can be edited
+//! without a restart. A template that fails to load is reported and the previous one kept.
+use minijinja::{AutoEscape, Environment, UndefinedBehavior};
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+use std::time::SystemTime;
+use time::OffsetDateTime;
+
+use crate::context::{Context, File, Other};
+use crate::profile::Profile;
+use crate::variants::{Param, Source, Variant};
+
+const SYSTEM: &str = "system.jinja";
+const USER: &str = "user.jinja";
+
+const BUILTIN: [(&str, &str); 2] = [
+    (SYSTEM, include_str!("../prompts/system.jinja")),
+    (USER, include_str!("../prompts/user.jinja")),
+];
+
+pub struct Prompts {
+    dir: PathBuf,
+    vhost_suffix: Option<String>,
+    loaded: RwLock<Loaded>,
+}
+
+struct Loaded {
+    env: Environment<'static>,
+    version: String,
+    /// Modification times of the files the templates were read from, `None` for built-in ones.
+    stamps: Vec<Option<SystemTime>>,
+}
+
+/// Variables available to both templates.
+#[derive(Serialize)]
+struct Variables<'a> {
+    date: String,
+    url: String,
+    domain: String,
+    extension: String,
+    assets: &'a [File],
+    others: &'a [Other],
+    omitted: usize,
+    query: &'a [Param],
+    form: &'a [Param],
+    profile: &'a str,
+    instructions: Option<&'a str>,
+    vhost_suffix: Option<&'a str>,
+}
+
+pub struct Prompt {
+    pub system: String,
+    pub user: String,
+    /// Identifies the templates, so a page can be traced back to the prompt that made it.
+    pub version: String,
+}
+
+impl Prompts {
+    /// `vhost_suffix` is `VHOST_SUFFIX`, which changes how pages link to each other.
+    pub fn load(dir: impl Into<PathBuf>, vhost_suffix: Option<String>) -> Result<Self, String> {
+        let dir = dir.into();
+        let loaded = Loaded::read(&dir)?;
+
+        Ok(Self {
+            dir,
+            vhost_suffix: vhost_suffix.map(|suffix| suffix.trim_start_matches('.').to_string()),
+            loaded: RwLock::new(loaded),
+        })
+    }
+
+    /// Renders the prompts for generating `path`, e.g. `example.com/index.html`, or the
+    /// `variant` of it asked for by query parameters or a form.
+    pub fn render(
+        &self,
+        path: &Path,
+        variant: Option<&Variant>,
+        context: &Context,
+        profile: &Profile,
+    ) -> Result<Prompt, String> {
+        self.reload();
+
+        let variables = Variables {
+            date: OffsetDateTime::now_utc().date().to_string(),
+            url: path.to_string_lossy().into_owned(),
+            domain: path
+                .iter()
+                .next()
+                .map(|domain| domain.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            extension: path
+                .extension()
+                .map(|extension| extension.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            assets: &context.files,
+            others: &context.others,
+            omitted: context.omitted,
+            query: variant
+                .and_then(Variant::selected)
+                .map_or(&[], |variant| &variant.params),
+            form: variant
+                .filter(|variant| variant.source == Source::Form)
+                .map_or(&[], |variant| &variant.params),
+            profile: &profile.name,
+            instructions: profile.instructions.as_deref(),
+            vhost_suffix: self.vhost_suffix.as_deref(),
+        };
+
+        let loaded = self.loaded.read().unwrap();
+
+        Ok(Prompt {
+            system: loaded.render(SYSTEM, &variables)?,
+            user: loaded.render(USER, &variables)?,
+            version: loaded.version.clone(),
+        })
+    }
+
+    fn reload(&self) {
+        let stamps = stamps(&self.dir);
+        if self.loaded.read().unwrap().stamps == stamps {
+            return;
+        }
+
+        match Loaded::read(&self.dir) {
+            Ok(loaded) => {
+                eprintln!("reloaded prompt templates, version {}", loaded.version);
+                *self.loaded.write().unwrap() = loaded;
+            }
+            Err(e) => {
+                eprintln!("keeping the previous prompt templates, {e}");
+                // Don't report the same broken file on every request
+                self.loaded.write().unwrap().stamps = stamps;
+            }
+        }
+    }
+}
+
+impl Loaded {
+    fn read(dir: &Path) -> Result<Self, String> {
+        // Taken first, so an edit made while reading is picked up next time
+        let stamps = stamps(dir);
+
+        let mut env = Environment::new();
+        env.set_undefined_behavior(UndefinedBehavior::Strict);
+        env.set_auto_escape_callback(|_| AutoEscape::None);
+        env.set_trim_blocks(true);
+
+        let mut sources = Vec::new();
+
+        for ((name, builtin), stamp) in BUILTIN.into_iter().zip(&stamps) {
+            let file = dir.join(name);
+
+            let source = match stamp {
+                Some(_) => std::fs::read_to_string(&file)
+                    .map_err(|e| format!("cannot read {}: {e}", file.display()))?,
+                None => builtin.to_string(),
+            };
+
+            env.add_template_owned(name, source.clone())
+                .map_err(|e| format!("invalid template {}: {e:#}", file.display()))?;
+            sources.push((name, source));
+        }
+
+        let hash = fnv1a(
+            sources
+                .iter()
+                .flat_map(|(name, source)| name.bytes().chain(source.bytes())),
+        );
+
+        let loaded = Self {
+            env,
+            version: format!("{hash:016x}"),
+            stamps,
+        };
+
+        // Surface unknown variables and the like now rather than on the next generation, with
+        // and without virtual hosts
+        let mut example = Variables {
+            date: "2050-01-01".into(),
+            url: "example.com/index.html".into(),
+            domain: "example.com".into(),
+            extension: "html".into(),
+            assets: &[],
+            others: &[],
+            omitted: 0,
+            query: &[],
+            form: &[],
+            profile: "html",
+            instructions: Some("Write HTML."),
+            vhost_suffix: None,
+        };
+
+        for vhost_suffix in [None, Some("web2050.local")] {
+            example.vhost_suffix = vhost_suffix;
+
+            for (name, _) in BUILTIN {
+                loaded
+                    .render(name, &example)
+                    .map_err(|e| format!("{e} (in {})", dir.display()))?;
+            }
+        }
+
+        Ok(loaded)
+    }
+
+    fn render(&self, name: &str, variables: &Variables) -> Result<String, String> {
+        self.env
+            .get_template(name)
+            .and_then(|template| template.render(variables))
+            .map_err(|e| format!("cannot render {name}: {e:#}"))
+    }
+}
+
+/// FNV-1a of `bytes`, stable across builds unlike `DefaultHasher`.
+pub fn fnv1a(bytes: impl IntoIterator<Item = u8>) -> u64 {
+    bytes.into_iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+fn stamps(dir: &Path) -> Vec<Option<SystemTime>> {
+    BUILTIN
+        .iter()
+        .map(|(name, _)| {
+            std::fs::metadata(dir.join(name))
+                .and_then(|metadata| metadata.modified())
+                .ok()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    /// A prompt directory, removed when dropped.
+    struct Dir(PathBuf);
+
+    impl Dir {
+        fn new(name: &str) -> Self {
+            let dir =
+                std::env::temp_dir().join(format!("web2050-prompt-{name}-{}", std::process::id()));
+            let _ = std::fs::remove_dir_all(&dir);
+            std::fs::create_dir_all(&dir).unwrap();
+            Self(dir)
+        }
+
+        /// Writes a template with a modification time of its own, since a quick rewrite could
+        /// otherwise keep the previous one.
+        fn write(&self, name: &str, source: &str, seconds: u64) {
+            let path = self.0.join(name);
+            std::fs::write(&path, source).unwrap();
+            std::fs::File::options()
+                .write(true)
+                .open(&path)
+                .unwrap()
+                .set_modified(SystemTime::UNIX_EPOCH + Duration::from_secs(seconds))
+                .unwrap();
+        }
+    }
+
+    impl Drop for Dir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn render(prompts: &Prompts) -> Prompt {
+        let context = Context {
+            files: Vec::new(),
+            others: Vec::new(),
+            omitted: 0,
+        };
+        let profile = Profile {
+            name: "html".into(),
+            ..Profile::default()
+        };
+
+        prompts
+            .render(
+                Path::new("example.com/index.html"),
+                None,
+                &context,
+                &profile,
+            )
+            .unwrap()
+    }
+
+    #[test]
+    fn missing_templates_fall_back_to_the_builtin_ones() {
+        let dir = Dir::new("builtin");
+        let prompt = render(&Prompts::load(&dir.0, None).unwrap());
+
+        assert!(
+            prompt
+                .user
+                .contains("URL to create: example.com/index.html")
+        );
+        assert_eq!(
+            prompt.version,
+            render(&Prompts::load(dir.0.join("missing"), None).unwrap()).version
+        );
+    }
+
+    #[test]
+    fn edited_templates_are_reloaded() {
+        let dir = Dir::new("reload");
+        let prompts = Prompts::load(&dir.0, None).unwrap();
+        let builtin = render(&prompts);
+
+        dir.write(USER, "Create {{ url }}", 1);
+        let edited = render(&prompts);
+        assert_eq!(edited.user, "Create example.com/index.html");
+        assert_eq!(edited.system, builtin.system);
+        assert_ne!(edited.version, builtin.version);
+
+        dir.write(USER, "Make {{ url }}", 2);
+        let again = render(&prompts);
+        assert_eq!(again.user, "Make example.com/index.html");
+        assert_ne!(again.version, edited.version);
+
+        // Back to the built-in one
+        std::fs::remove_file(dir.0.join(USER)).unwrap();
+        assert_eq!(render(&prompts).version, builtin.version);
+    }
+
+    #[test]
+    fn broken_templates_keep_the_previous_ones() {
+        let dir = Dir::new("broken");
+        dir.write(USER, "Create {{ url }}", 1);
+        let prompts = Prompts::load(&dir.0, None).unwrap();
+        let working = render(&prompts);
+
+        // A syntax error, and a variable that doesn't exist
+        for (source, seconds) in [("Create {{ url", 2), ("Create {{ address }}", 3)] {
+            dir.write(USER, source, seconds);
+            let prompt = render(&prompts);
+            assert_eq!(prompt.user, working.user);
+            assert_eq!(prompt.version, working.version);
+        }
+
+        // Only a running server has previous templates to keep
+        assert!(Prompts::load(&dir.0, None).is_err());
+
+        dir.write(USER, "Make {{ url }}", 4);
+        assert_eq!(render(&prompts).user, "Make example.com/index.html");
+    }
+}
@@ -0,0 +1,1001 @@
+//! Rewrites the links of generated HTML and CSS while they stream.
+//!
+//! Models don't always follow the linking policy. They write `https://example.com/about.html`,
+//! relative links or CDN URLs, which then break or are blocked by the CSP. So `href`, `src`,
+//! `srcset` and `action` attributes, inline styles, `url()` and `@import` are normalized to local
+//! paths like `/example.com/about.html`. With virtual hosts, links within a site become
+//! root-relative and links to other sites `//other.com.<suffix>/...`.
+//!
+//! Root-relative links are already local when they start with a host, like
+//! `/github.com/webassembly`. Otherwise they belong to the current site, so `/v1.2/docs.html` is
+//! `/example.com/v1.2/docs.html`.
+//!
+//! Links to pages on other hosts lead to their generated counterparts. Resources loaded from
+//! other hosts, like scripts, stylesheets, fonts and images, are removed instead, since they
+//! would only be blocked: their attribute, `srcset` candidate or `@import` is dropped.
+//!
+//! Output is only held back until the tag or CSS rule in progress is complete, so pages still
+//! stream.
+use mime_guess::{Mime, mime};
+use std::path::Path;
+
+use crate::vhost::{self, VirtualHosts};
+
+/// Extensions of files linked root-relative, e.g. `/about.html`, as opposed to hosts like
+/// `/example.com`.
+const FILE_EXTENSIONS: [&str; 19] = [
+    "html", "htm", "css", "js", "mjs", "json", "svg", "txt", "md", "xml", "ico", "png", "jpg",
+    "jpeg", "gif", "webp", "woff", "woff2", "ttf",
+];
+
+pub struct LinkRewriter {
+    mode: Mode,
+    links: Links,
+    /// Input not rewritten yet, because it ends in an incomplete tag or rule.
+    buffer: String,
+    /// The element HTML text belongs to.
+    element: Element,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    Html,
+    Css,
+    Verbatim,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Element {
+    Other,
+    Style,
+    Script,
+}
+
+/// What becomes of a link.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Rewrite {
+    Keep,
+    Replace(String),
+    /// A resource of another host.
+    Drop,
+}
+
+/// Whether a link is followed by the visitor or loaded with the page.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Navigation,
+    Resource,
+}
+
+struct Links {
+    domain: String,
+    /// Of the page within its domain, e.g. `/blog/`.
+    dir: String,
+    vhosts: Option<VirtualHosts>,
+}
+
+impl LinkRewriter {
+    /// For the file stored at `page`, e.g. `example.com/blog/index.html`. Files other than HTML
+    /// and CSS pass through unchanged.
+    pub fn new(page: &Path, mime_type: &Mime, vhosts: Option<&VirtualHosts>) -> Self {
+        let mode = match (mime_type.type_(), mime_type.subtype()) {
+            (mime::TEXT, mime::HTML) => Mode::Html,
+            (mime::TEXT, mime::CSS) => Mode::Css,
+            _ => Mode::Verbatim,
+        };
+
+        let mut components = page.iter();
+        let domain = components.next().unwrap_or_default();
+
+        let mut dir = String::from("/");
+        if let Some(parent) = components.as_path().parent() {
+            for component in parent {
+                dir.push_str(&component.to_string_lossy());
+                dir.push('/');
+            }
+        }
+
+        Self {
+            mode,
+            links: Links {
+                domain: domain.to_string_lossy().into_owned(),
+                dir,
+                vhosts: vhosts.cloned(),
+            },
+            buffer: String::new(),
+            element: Element::Other,
+        }
+    }
+
+    /// Rewrites as much of the output so far as is complete.
+    pub fn feed(&mut self, chunk: &str) -> String {
+        match self.mode {
+            Mode::Verbatim => chunk.to_string(),
+            Mode::Css => {
+                self.buffer.push_str(chunk);
+                let rules: String = self.buffer.drain(..css_boundary(&self.buffer)).collect();
+                self.links.css(&rules)
+            }
+            Mode::Html => {
+                self.buffer.push_str(chunk);
+                self.html()
+            }
+        }
+    }
+
+    /// Rewrites whatever is left once the output is complete.
+    pub fn finish(&mut self) -> String {
+        let rest = std::mem::take(&mut self.buffer);
+
+        match (self.mode, self.element) {
+            (Mode::Css, _) | (Mode::Html, Element::Style) => self.links.css(&rest),
+            _ => rest,
+        }
+    }
+
+    fn html(&mut self) -> String {
+        let mut output = String::new();
+
+        loop {
+            if self.element == Element::Script {
+                // Scripts are left alone up to their end tag
+                match find_ignore_case(&self.buffer, "</script") {
+                    Some(end) => {
+                        output.extend(self.buffer.drain(..end));
+                        self.element = Element::Other;
+                    }
+                    None => {
+                        // Keeping enough for an end tag cut in half
+                        let mut keep = self.buffer.len().saturating_sub("</script".len());
+                        while !self.buffer.is_char_boundary(keep) {
+                            keep -= 1;
+                        }
+                        output.extend(self.buffer.drain(..keep));
+                        break;
+                    }
+                }
+            }
+
+            let Some(start) = self.buffer.find('<') else {
+                let end = match self.element {
+                    Element::Style => css_boundary(&self.buffer),
+                    _ => self.buffer.len(),
+                };
+                let text: String = self.buffer.drain(..end).collect();
+                output.push_str(&self.text(&text));
+                break;
+            };
+
+            let text: String = self.buffer.drain(..start).collect();
+            output.push_str(&self.text(&text));
+
+            // `a < b` is text, not a tag
+            match self.buffer[1..].chars().next() {
+                None => break,
+                Some(c) if !(c.is_ascii_alphabetic() || matches!(c, '/' | '!' | '?')) => {
+                    output.push_str(&self.buffer.drain(..1).collect::<String>());
+                    continue;
+                }
+                Some(_) => {}
+            }
+
+            let Some(end) = tag_end(&self.buffer) else {
+                break;
+            };
+            let tag: String = self.buffer.drain(..end).collect();
+            output.push_str(&self.tag(&tag));
+        }
+
+        output
+    }
+
+    fn text(&self, text: &str) -> String {
+        match self.element {
+            Element::Style => self.links.css(text),
+            _ => text.to_string(),
+        }
+    }
+
+    /// Rewrites the attributes of a complete tag, from `<` to `>`.
+    fn tag(&mut self, tag: &str) -> String {
+        if tag.starts_with("<!") || tag.starts_with("<?") {
+            return tag.to_string();
+        }
+
+        let closing = tag.starts_with("</");
+        let name_start = if closing { 2 } else { 1 };
+        let name_end = tag[name_start..]
+            .find(|c: char| c.is_ascii_whitespace() || c == '/' || c == '>')
+            .map_or(tag.len(), |end| name_start + end);
+        let name = tag[name_start..name_end].to_ascii_lowercase();
+
+        if closing {
+            self.element = Element::Other;
+            return tag.to_string();
+        }
+
+        self.element = match name.as_str() {
+            "style" => Element::Style,
+            "script" if !tag.ends_with("/>") => Element::Script,
+            _ => Element::Other,
+        };
+
+        let mut output = String::with_capacity(tag.len());
+        let mut copied = 0;
+
+        for attribute in attributes(tag, name_end) {
+            let (start, end) = attribute.value;
+            let value = &tag[start..end];
+
+            let rewrite = match (name.as_str(), attribute.name.as_str()) {
+                ("a" | "area", "href") | ("form", "action") | ("iframe" | "frame", "src") => {
+                    self.links.url(value, Kind::Navigation)
+                }
+                (_, "href" | "src" | "action") => self.links.url(value, Kind::Resource),
+                (_, "srcset" | "imagesrcset") => self.links.srcset(value),
+                (_, "style") => Rewrite::Replace(self.links.css(value)),
+                _ => Rewrite::Keep,
+            };
+
+            match rewrite {
+                Rewrite::Keep => {}
+                Rewrite::Replace(url) => {
+                    let quoted = matches!(tag[..start].chars().last(), Some('"' | '\''));
+
+                    output.push_str(&tag[copied..start]);
+                    match quoted {
+                        true => output.push_str(&url),
+                        false => output.push_str(&format!("\"{url}\"")),
+                    }
+                    copied = end;
+                }
+                Rewrite::Drop => {
+                    // Along with the whitespace separating it from the previous one
+                    let before = tag[..attribute.start].trim_end().len();
+                    output.push_str(&tag[copied..before]);
+                    copied = attribute.end;
+                }
+            }
+        }
+
+        output.push_str(&tag[copied..]);
+        output
+    }
+}
+
+impl Links {
+    /// The local form of `target`.
+    fn url(&self, target: &str, kind: Kind) -> Rewrite {
+        let target = target.trim();
+        if target.is_empty() || target.starts_with(['#', '?']) {
+            return Rewrite::Keep;
+        }
+
+        let (location, suffix) = target.split_at(target.find(['?', '#']).unwrap_or(target.len()));
+
+        let (domain, path) = if let Some(rest) = strip_scheme(location) {
+            let (authority, path) = rest.split_at(rest.find('/').unwrap_or(rest.len()));
+            let host = authority.rsplit('@').next().unwrap_or_default();
+            let host = host.split(':').next().unwrap_or_default();
+            let host = host.trim_end_matches('.').to_ascii_lowercase();
+
+            if host.is_empty() {
+                return Rewrite::Keep;
+            }
+
+            let domain = match &self.vhosts {
+                Some(vhosts) => vhosts.domain(&host).unwrap_or(host),
+                None => host,
+            };
+
+            if domain != self.domain && kind == Kind::Resource {
+                return Rewrite::Drop;
+            }
+
+            (domain, path.to_string())
+        } else if has_scheme(location) {
+            // data:, mailto:, javascript: and the like
+            return Rewrite::Keep;
+        } else if let Some(rest) = location.strip_prefix('/') {
+            if self.vhosts.is_some() || vhost::shared(location) {
+                return Rewrite::Keep;
+            }
+
+            // Already local, e.g. `/example.com/about.html` or `/github.com/webassembly`
+            let (first, more) = match rest.split_once('/') {
+                Some((first, _)) => (first, true),
+                None => (rest, false),
+            };
+            if first.eq_ignore_ascii_case(&self.domain) || is_host(first, more) {
+                return Rewrite::Keep;
+            }
+
+            (self.domain.clone(), location.to_string())
+        } else {
+            (self.domain.clone(), resolve(&self.dir, location))
+        };
+
+        Rewrite::Replace(format!("{}{suffix}", self.local(&domain, &path)))
+    }
+
+    /// Rewrites the URLs of the image candidates of a `srcset`, e.g. `a.svg 1x, b.svg 2x`.
+    /// Candidates on other hosts are left out.
+    fn srcset(&self, srcset: &str) -> Rewrite {
+        let mut candidates = Vec::new();
+        let mut changed = false;
+
+        for (url, descriptors) in candidates_of(srcset) {
+            let url = match self.url(url, Kind::Resource) {
+                Rewrite::Keep => url.to_string(),
+                Rewrite::Replace(url) => {
+                    changed = true;
+                    url
+                }
+                Rewrite::Drop => {
+                    changed = true;
+                    continue;
+                }
+            };
+
+            candidates.push(match descriptors.is_empty() {
+                true => url,
+                false => format!("{url} {descriptors}"),
+            });
+        }
+
+        match (changed, candidates.is_empty()) {
+            (false, _) => Rewrite::Keep,
+            (true, true) => Rewrite::Drop,
+            (true, false) => Rewrite::Replace(candidates.join(", ")),
+        }
+    }
+
+    /// Where the page at `path` of `domain` is linked from the current page.
+    fn local(&self, domain: &str, path: &str) -> String {
+        match &self.vhosts {
+            Some(_) if domain == self.domain && path.is_empty() => "/".to_string(),
+            Some(_) if domain == self.domain => path.to_string(),
+            Some(vhosts) => vhosts.url(domain, path),
+            None => format!("/{domain}{path}"),
+        }
+    }
+
+    /// Rewrites the `url()` and `@import` targets of complete CSS.
+    fn css(&self, css: &str) -> String {
+        let lower = css.to_ascii_lowercase();
+        let mut output = String::with_capacity(css.len());
+        let mut copied = 0;
+        let mut at = 0;
+
+        loop {
+            let next_url = lower[at..].find("url(").map(|i| at + i);
+            let next_import = lower[at..].find("@import").map(|i| at + i);
+
+            let (start, import) = match (next_url, next_import) {
+                (Some(url), Some(import)) if import < url => (import, true),
+                (Some(url), _) => (url, false),
+                (None, Some(import)) => (import, true),
+                (None, None) => break,
+            };
+
+            if import {
+                let statement_end = css[start..]
+                    .find(';')
+                    .map_or(css.len(), |end| start + end + 1);
+                let after = start + "@import".len();
+                let rest = css[after..statement_end].trim_start();
+                let target_start = statement_end - rest.len();
+
+                let (value_start, value_end) = if rest.to_ascii_lowercase().starts_with("url(") {
+                    match url_value(css, target_start + "url(".len()) {
+                        Some(span) => span,
+                        None => break,
+                    }
+                } else {
+                    match quoted_value(css, target_start) {
+                        Some(span) => span,
+                        None => {
+                            at = target_start;
+                            continue;
+                        }
+                    }
+                };
+
+                match self.url(&css[value_start..value_end], Kind::Resource) {
+                    Rewrite::Drop => {
+                        output.push_str(&css[copied..start]);
+                        copied = statement_end;
+                        at = statement_end;
+                    }
+                    Rewrite::Replace(url) => {
+                        output.push_str(&css[copied..value_start]);
+                        output.push_str(&url);
+                        copied = value_end;
+                        at = value_end;
+                    }
+                    Rewrite::Keep => at = value_end,
+                }
+            } else {
+                let Some((value_start, value_end)) = url_value(css, start + "url(".len()) else {
+                    break;
+                };
+
+                // There is nothing to drop in a declaration, so blocked resources are emptied
+                let url = match self.url(&css[value_start..value_end], Kind::Resource) {
+                    Rewrite::Keep => None,
+                    Rewrite::Replace(url) => Some(url),
+                    Rewrite::Drop => Some(String::new()),
+                };
+                if let Some(url) = url {
+                    output.push_str(&css[copied..value_start]);
+                    output.push_str(&url);
+                    copied = value_end;
+                }
+                at = value_end;
+            }
+        }
+
+        output.push_str(&css[copied..]);
+        output
+    }
+}
+
+/// An attribute with a value, within a tag.
+struct Attribute {
+    /// Lowercase.
+    name: String,
+    /// Where its name starts.
+    start: usize,
+    /// Span of the value, without quotes.
+    value: (usize, usize),
+    /// After the value and its closing quote.
+    end: usize,
+}
+
+/// The attributes of a tag with values, after its name.
+fn attributes(tag: &str, from: usize) -> Vec<Attribute> {
+    let bytes = tag.as_bytes();
+    let mut attributes = Vec::new();
+    let mut i = from;
+
+    while i < bytes.len() {
+        while i < bytes.len() && (bytes[i].is_ascii_whitespace() || bytes[i] == b'/') {
+            i += 1;
+        }
+        if i >= bytes.len() || bytes[i] == b'>' {
+            break;
+        }
+
+        let name_start = i;
+        while i < bytes.len()
+            && !matches!(bytes[i], b'=' | b'>' | b'/')
+            && !bytes[i].is_ascii_whitespace()
+        {
+            i += 1;
+        }
+        let name = tag[name_start..i].to_ascii_lowercase();
+
+        while i < bytes.len() && bytes[i].is_ascii_whitespace() {
+            i += 1;
+        }
+        if i >= bytes.len() || bytes[i] != b'=' {
+            continue;
+        }
+        i += 1;
+        while i < bytes.len() && bytes[i].is_ascii_whitespace() {
+            i += 1;
+        }
+
+        match bytes.get(i) {
+            Some(&quote @ (b'"' | b'\'')) => {
+                let start = i + 1;
+                let end = tag[start..]
+                    .find(quote as char)
+                    .map_or(tag.len(), |end| start + end);
+                i = (end + 1).min(tag.len());
+                attributes.push(Attribute {
+                    name,
+                    start: name_start,
+                    value: (start, end),
+                    end: i,
+                });
+            }
+            Some(_) => {
+                let start = i;
+                while i < bytes.len() && !bytes[i].is_ascii_whitespace() && bytes[i] != b'>' {
+                    i += 1;
+                }
+                attributes.push(Attribute {
+                    name,
+                    start: name_start,
+                    value: (start, i),
+                    end: i,
+                });
+            }
+            None => break,
+        }
+    }
+
+    attributes
+}
+
+/// The length of the tag `html` starts with, if it is complete.
+fn tag_end(html: &str) -> Option<usize> {
+    if html.starts_with("<!--") {
+        return html.find("-->").map(|end| end + "-->".len());
+    }
+    if "<!--".starts_with(html) {
+        return None;
+    }
+
+    let bytes = html.as_bytes();
+    let mut i = 1;
+
+    while i < bytes.len() {
+        match bytes[i] {
+            b'>' => return Some(i + 1),
+            b'=' => {
+                i += 1;
+                while i < bytes.len() && bytes[i].is_ascii_whitespace() {
+                    i += 1;
+                }
+                if let Some(&quote @ (b'"' | b'\'')) = bytes.get(i) {
+                    i += 1 + html[i + 1..].find(quote as char)?;
+                }
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+
+    None
+}
+
+/// How much of `css` is complete rules, i.e. up to its last `;`, brace or line break.
+fn css_boundary(css: &str) -> usize {
+    css.rfind([';', '{', '}', '\n']).map_or(0, |end| end + 1)
+}
+
+/// The span of the target of a `url(` whose content starts at `start`, without quotes.
+fn url_value(css: &str, start: usize) -> Option<(usize, usize)> {
+    let rest = &css[start..];
+    let trimmed = rest.trim_start();
+    let start = start + rest.len() - trimmed.len();
+
+    match quoted_value(css, start) {
+        Some(span) => Some(span),
+        None => {
+            let end = css[start..].find(')')?;
+            Some((start, start + css[start..start + end].trim_end().len()))
+        }
+    }
+}
+
+/// The span of the content of the quoted string at `start`.
+fn quoted_value(css: &str, start: usize) -> Option<(usize, usize)> {
+    let quote = css[start..]
+        .chars()
+        .next()
+        .filter(|c| matches!(c, '"' | '\''))?;
+    let end = css[start + 1..].find(quote)?;
+    Some((start + 1, start + 1 + end))
+}
+
+/// `host/path` of an `http:`, `https:` or protocol-relative URL.
+fn strip_scheme(url: &str) -> Option<&str> {
+    let lower = url.get(..8).unwrap_or(url).to_ascii_lowercase();
+
+    if lower.starts_with("https://") {
+        Some(&url[8..])
+    } else if lower.starts_with("http://") {
+        Some(&url[7..])
+    } else {
+        url.strip_prefix("//")
+    }
+}
+
+fn has_scheme(url: &str) -> bool {
+    url.split_once(':').is_some_and(|(scheme, _)| {
+        !scheme.is_empty()
+            && scheme
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'))
+    })
+}
+
+/// Whether the first segment of a root-relative path is a host rather than a directory or file of
+/// the site: dot-separated labels ending in an alphabetic top-level domain, so not `v1.2`, and
+/// not a file name like `about.html` unless a path follows.
+fn is_host(segment: &str, more: bool) -> bool {
+    let Some((_, tld)) = segment.rsplit_once('.') else {
+        return false;
+    };
+
+    let labels = segment.split('.').all(|label| {
+        !label.is_empty()
+            && label
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b == b'-')
+    });
+
+    labels
+        && tld.len() >= 2
+        && tld.bytes().all(|b| b.is_ascii_alphabetic())
+        && (more || !FILE_EXTENSIONS.contains(&tld.to_ascii_lowercase().as_str()))
+}
+
+/// The URLs and descriptors of the candidates of a `srcset`. A URL ends at whitespace, or at a
+/// comma ending it, so `data:` URLs keep theirs.
+fn candidates_of(srcset: &str) -> Vec<(&str, &str)> {
+    let mut candidates = Vec::new();
+    let mut rest = srcset;
+
+    loop {
+        rest = rest.trim_start_matches(|c: char| c.is_ascii_whitespace() || c == ',');
+        if rest.is_empty() {
+            break;
+        }
+
+        let url_end = rest
+            .find(|c: char| c.is_ascii_whitespace())
+            .unwrap_or(rest.len());
+        let url = &rest[..url_end];
+
+        if let Some(url) = url.strip_suffix(',') {
+            candidates.push((url.trim_end_matches(','), ""));
+            rest = &rest[url_end..];
+            continue;
+        }
+
+        // Descriptors run to the next comma outside of parentheses
+        let mut depth = 0;
+        let descriptors_end = rest[url_end..]
+            .char_indices()
+            .find(|&(_, c)| {
+                match c {
+                    '(' => depth += 1,
+                    ')' => depth -= 1,
+                    _ => {}
+                }
+                c == ',' && depth <= 0
+            })
+            .map_or(rest.len(), |(i, _)| url_end + i);
+
+        candidates.push((url, rest[url_end..descriptors_end].trim()));
+        rest = &rest[descriptors_end..];
+    }
+
+    candidates
+}
+
+/// `relative` resolved against the directory `dir`, e.g. `/blog/` and `../about.html` to
+/// `/about.html`. Never above the domain.
+fn resolve(dir: &str, relative: &str) -> String {
+    let mut segments: Vec<&str> = dir.split('/').filter(|s| !s.is_empty()).collect();
+
+    let parts: Vec<&str> = relative.split('/').collect();
+    for part in &parts {
+        match *part {
+            "." => {}
+            ".." => {
+                segments.pop();
+            }
+            "" => {}
+            part => segments.push(part),
+        }
+    }
+
+    let mut path = format!("/{}", segments.join("/"));
+    if matches!(parts.last(), Some(&("" | "." | ".."))) && !path.ends_with('/') {
+        path.push('/');
+    }
+    path
+}
+
+/// Finds `needle`, which must be lowercase ASCII, in `haystack` regardless of case.
+fn find_ignore_case(haystack: &str, needle: &str) -> Option<usize> {
+    haystack
+        .as_bytes()
+        .windows(needle.len())
+        .position(|window| window.eq_ignore_ascii_case(needle.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rewriter(page: &str, vhosts: Option<&VirtualHosts>) -> LinkRewriter {
+        let mime_type = mime_guess::from_path(page).first_or_octet_stream();
+        LinkRewriter::new(Path::new(page), &mime_type, vhosts)
+    }
+
+    /// Feeds `input` in chunks of `size` bytes, cut wherever that falls.
+    fn rewrite_in_chunks(
+        page: &str,
+        vhosts: Option<&VirtualHosts>,
+        input: &str,
+        size: usize,
+    ) -> String {
+        let mut rewriter = rewriter(page, vhosts);
+        let mut output = String::new();
+        let mut rest = input;
+
+        while !rest.is_empty() {
+            let mut end = size.min(rest.len());
+            while !rest.is_char_boundary(end) {
+                end += 1;
+            }
+            output.push_str(&rewriter.feed(&rest[..end]));
+            rest = &rest[end..];
+        }
+
+        output + &rewriter.finish()
+    }
+
+    fn rewrite(page: &str, input: &str) -> String {
+        rewrite_in_chunks(page, None, input, input.len().max(1))
+    }
+
+    #[test]
+    fn attributes_are_made_local() {
+        let page = "example.com/blog/index.html";
+
+        assert_eq!(
+            rewrite(page, r#"<a href="https://example.com/about.html">"#),
+            r#"<a href="/example.com/about.html">"#
+        );
+        assert_eq!(
+            rewrite(page, r#"<a class=x href='../about.html?x=1#top'>"#),
+            r#"<a class=x href='/example.com/about.html?x=1#top'>"#
+        );
+        assert_eq!(
+            rewrite(page, "<a href=post.html>"),
+            r#"<a href="/example.com/blog/post.html">"#
+        );
+        assert_eq!(
+            rewrite(page, r#"<form action="//Other.com/login">"#),
+            r#"<form action="/other.com/login">"#
+        );
+        assert_eq!(
+            rewrite(page, r##"<a href="#top"><a href="mailto:a@b.c">"##),
+            r##"<a href="#top"><a href="mailto:a@b.c">"##
+        );
+    }
+
+    #[test]
+    fn root_relative_links_belong_to_the_site() {
+        let page = "example.com/index.html";
+
+        assert_eq!(
+            rewrite(page, r#"<a href="/v1.2/docs.html">"#),
+            r#"<a href="/example.com/v1.2/docs.html">"#
+        );
+        assert_eq!(
+            rewrite(page, r#"<a href="/about.html">"#),
+            r#"<a href="/example.com/about.html">"#
+        );
+        assert_eq!(
+            rewrite(page, r#"<a href="/docs/v2.0/">"#),
+            r#"<a href="/example.com/docs/v2.0/">"#
+        );
+        // Already local, or shared by all sites
+        assert_eq!(
+            rewrite(page, r#"<a href="/example.com/about.html">"#),
+            r#"<a href="/example.com/about.html">"#
+        );
+        assert_eq!(
+            rewrite(page, r#"<a href="/github.com/webassembly">"#),
+            r#"<a href="/github.com/webassembly">"#
+        );
+        assert_eq!(
+            rewrite(page, r#"<a href="/docs.rs">"#),
+            r#"<a href="/docs.rs">"#
+        );
+        assert_eq!(
+            rewrite(
+                page,
+                r#"<link rel=stylesheet href="/cdn.example.net/x.css">"#
+            ),
+            r#"<link rel=stylesheet href="/cdn.example.net/x.css">"#
+        );
+        assert_eq!(
+            rewrite(page, r#"<script src="/tailwindcss.js"></script>"#),
+            r#"<script src="/tailwindcss.js"></script>"#
+        );
+    }
+
+    #[test]
+    fn resources_of_other_hosts_are_dropped() {
+        let page = "example.com/index.html";
+
+        assert_eq!(
+            rewrite(
+                page,
+                r#"<script src="https://cdn.example.net/x.js" defer></script>"#
+            ),
+            "<script defer></script>"
+        );
+        assert_eq!(
+            rewrite(
+                page,
+                r#"<link rel=stylesheet href=https://cdn.example.net/x.css>"#
+            ),
+            "<link rel=stylesheet>"
+        );
+        assert_eq!(
+            rewrite(page, r#"<img alt="" src="https://example.com/logo.svg"/>"#),
+            r#"<img alt="" src="/example.com/logo.svg"/>"#
+        );
+        // Pages of other hosts are generated instead
+        assert_eq!(
+            rewrite(page, r#"<a href="https://other.com/">"#),
+            r#"<a href="/other.com/">"#
+        );
+    }
+
+    #[test]
+    fn srcset_candidates_are_rewritten() {
+        let page = "example.com/index.html";
+
+        assert_eq!(
+            rewrite(
+                page,
+                r#"<img srcset="logo.svg 1x, https://cdn.example.net/logo.svg 2x,/big.svg 600w">"#
+            ),
+            r#"<img srcset="/example.com/logo.svg 1x, /example.com/big.svg 600w">"#
+        );
+        assert_eq!(
+            rewrite(
+                page,
+                r#"<img src="a.svg" srcset="https://cdn.example.net/a.svg 2x">"#
+            ),
+            r#"<img src="/example.com/a.svg">"#
+        );
+        // A comma ending a URL separates candidates, others belong to it
+        assert_eq!(
+            rewrite(
+                page,
+                r#"<img srcset="data:image/svg+xml,x 1x,a.svg, b.svg 2x">"#
+            ),
+            r#"<img srcset="data:image/svg+xml,x 1x, /example.com/a.svg, /example.com/b.svg 2x">"#
+        );
+        assert_eq!(
+            rewrite(page, r#"<img srcset="/example.com/a.svg 1x">"#),
+            r#"<img srcset="/example.com/a.svg 1x">"#
+        );
+    }
+
+    #[test]
+    fn css_urls_and_imports_are_rewritten() {
+        let page = "example.com/css/site.css";
+
+        assert_eq!(
+            rewrite(page, "body { background: url( ../bg.svg ) }"),
+            "body { background: url( /example.com/bg.svg ) }"
+        );
+        assert_eq!(
+            rewrite(
+                page,
+                r#"a { background: URL("https://cdn.example.net/x.svg") }"#
+            ),
+            r#"a { background: URL("") }"#
+        );
+        assert_eq!(
+            rewrite(
+                page,
+                "@import 'base.css';\n@import url(\"https://fonts.example.net/f.css\");\nbody{}"
+            ),
+            "@import '/example.com/css/base.css';\n\nbody{}"
+        );
+        assert_eq!(
+            rewrite(page, "a { background: url(data:image/svg+xml;utf8,x) }"),
+            "a { background: url(data:image/svg+xml;utf8,x) }"
+        );
+    }
+
+    #[test]
+    fn styles_in_html_are_rewritten() {
+        let page = "example.com/index.html";
+
+        assert_eq!(
+            rewrite(page, r#"<div style="background: url('bg.svg')">"#),
+            r#"<div style="background: url('/example.com/bg.svg')">"#
+        );
+        assert_eq!(
+            rewrite(page, "<style>@import 'a.css'; p { x: url(b.svg) }</style>"),
+            "<style>@import '/example.com/a.css'; p { x: url(/example.com/b.svg) }</style>"
+        );
+        // Scripts are left alone
+        assert_eq!(
+            rewrite(page, r#"<script>let a = "<a href='x.html'>";</script>"#),
+            r#"<script>let a = "<a href='x.html'>";</script>"#
+        );
+    }
+
+    #[test]
+    fn tags_split_across_chunks() {
+        let page = "example.com/blog/index.html";
+        let html = concat!(
+            "<!doctype html><p>1 < 2</p><!-- <a href=x.html> -->",
+            r#"<a title="a > b" href="post.html">ü</a>"#,
+            r#"<img srcset="a.svg 1x, https://cdn.example.net/b.svg 2x" src="https://cdn.example.net/b.svg">"#,
+            "<style>p { background: url(bg.svg) }\n@import 'x.css';</style>",
+            "<script>if (a</script ) {}</script><p>ü</p>",
+        );
+        let expected = rewrite(page, html);
+
+        assert!(expected.contains(r#"<a title="a > b" href="/example.com/blog/post.html">"#));
+        assert!(expected.contains(r#"<img srcset="/example.com/blog/a.svg 1x">"#));
+        assert!(expected.contains("url(/example.com/blog/bg.svg)"));
+        assert!(expected.contains("<!-- <a href=x.html> -->"));
+
+        for size in 1..=8 {
+            assert_eq!(
+                rewrite_in_chunks(page, None, html, size),
+                expected,
+                "chunks of {size}"
+            );
+        }
+
+        let css =
+            "a{background:url(x.svg)}\n@import \"y.css\";b{c:url('https://cdn.example.net/z.svg')}";
+        let expected = rewrite("example.com/site.css", css);
+        for size in 1..=8 {
+            assert_eq!(
+                rewrite_in_chunks("example.com/site.css", None, css, size),
+                expected,
+                "chunks of {size}"
+            );
+        }
+    }
+
+    #[test]
+    fn virtual_hosts() {
+        let vhosts = VirtualHosts::new("web2050.local");
+        let rewrite = |input: &str| {
+            rewrite_in_chunks(
+                "example.com/blog/index.html",
+                Some(&vhosts),
+                input,
+                input.len(),
+            )
+        };
+
+        assert_eq!(
+            rewrite(r#"<a href="https://example.com/about.html">"#),
+            r#"<a href="/about.html">"#
+        );
+        assert_eq!(
+            rewrite(r#"<a href="https://example.com">"#),
+            r#"<a href="/">"#
+        );
+        assert_eq!(
+            rewrite(r#"<a href="post.html">"#),
+            r#"<a href="/blog/post.html">"#
+        );
+        assert_eq!(
+            rewrite(r#"<a href="/v1.2/docs.html">"#),
+            r#"<a href="/v1.2/docs.html">"#
+        );
+        assert_eq!(
+            rewrite(r#"<a href="https://other.com/x.html">"#),
+            r#"<a href="//other.com.web2050.local/x.html">"#
+        );
+        assert_eq!(
+            rewrite(r#"<a href="http://other.com.web2050.local/x.html">"#),
+            r#"<a href="//other.com.web2050.local/x.html">"#
+        );
+        // A resource of the site through its virtual host is still the site's
+        assert_eq!(
+            rewrite(r#"<img src="//example.com.web2050.local/a.svg">"#),
+            r#"<img src="/a.svg">"#
+        );
+        assert_eq!(rewrite(r#"<img src="https://other.com/a.svg">"#), "<img>");
+    }
+
+    #[test]
+    fn other_files_pass_through() {
+        let input = r#"{"href": "https://cdn.example.net/x.js"}"#;
+        assert_eq!(rewrite("example.com/data.json", input), input);
+    }
+}
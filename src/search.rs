@@ -0,0 +1,804 @@
+//! In-process full-text index over the generated files.
+//!
+//! Documents are the files inside domain directories. Markup is stripped before indexing, and
+//! the path itself is indexed too so pages can be found by name. Queries are ranked with BM25
+//! and support `"quoted phrases"`, `domain:` (or `site:`) and `ext:` filters.
+use jwalk::WalkDir;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::sync::RwLock;
+use std::time::SystemTime;
+
+use crate::store::Store;
+
+// The usual BM25 parameters
+const K1: f64 = 1.2;
+const B: f64 = 0.75;
+
+/// Tokens of context on each side of the first match in a snippet.
+const SNIPPET_RADIUS: usize = 16;
+
+/// Characters in a document summary.
+const SUMMARY_LENGTH: usize = 280;
+
+/// Snippets returned per hit.
+const MAX_SNIPPETS: usize = 3;
+
+#[derive(Debug, Clone)]
+pub struct Document {
+    /// Relative to the data directory, e.g. `example.com/index.html`.
+    pub path: String,
+    pub domain: String,
+    pub extension: String,
+    /// In bytes, on disk.
+    pub size: u64,
+    pub created: SystemTime,
+    /// The `<title>` of markup.
+    pub title: Option<String>,
+    /// The beginning of the text, past the title.
+    pub summary: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct Hit {
+    pub document: Document,
+    pub score: f64,
+    pub snippets: Vec<Snippet>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Snippet {
+    pub text: String,
+    /// Byte ranges of `text` that matched the query.
+    pub highlights: Vec<(usize, usize)>,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct Query {
+    terms: Vec<String>,
+    phrases: Vec<Vec<String>>,
+    domain: Option<String>,
+    extension: Option<String>,
+}
+
+struct Entry {
+    document: Document,
+    /// Extracted plain text, kept for phrases and snippets.
+    text: String,
+    length: usize,
+}
+
+#[derive(Default)]
+struct Inner {
+    entries: HashMap<u32, Entry>,
+    ids: HashMap<String, u32>,
+    next_id: u32,
+    /// term -> document -> token positions
+    postings: HashMap<String, HashMap<u32, Vec<u32>>>,
+    total_length: usize,
+}
+
+#[derive(Default)]
+pub struct SearchIndex {
+    inner: RwLock<Inner>,
+}
+
+impl SearchIndex {
+    /// Indexes every file below `root` that lives inside a domain directory. Blocking.
+    pub fn build(root: impl AsRef<Path>) -> Self {
+        let root = root.as_ref();
+        let index = Self::default();
+
+        for entry in WalkDir::new(root).min_depth(2).skip_hidden(true) {
+            let Ok(entry) = entry else { continue };
+            if !entry.file_type().is_file() {
+                continue;
+            }
+
+            let path = entry.path();
+            let Ok(rel) = path.strip_prefix(root) else {
+                continue;
+            };
+            let Ok(metadata) = std::fs::metadata(&path) else {
+                continue;
+            };
+            // Binary files are not searchable
+            let Ok(content) = std::fs::read_to_string(&path) else {
+                continue;
+            };
+
+            let created = metadata
+                .created()
+                .or_else(|_| metadata.modified())
+                .unwrap_or(SystemTime::UNIX_EPOCH);
+
+            index.update(&rel.to_string_lossy(), &content, created);
+        }
+
+        index
+    }
+
+    /// Adds or replaces the document at `path`.
+    pub fn update(&self, path: &str, content: &str, created: SystemTime) {
+        let mut inner = self.inner.write().unwrap();
+        inner.remove(path);
+
+        let (domain, extension) = split_path(path);
+        let text = extract_text(&extension, content);
+        let title = extract_title(&extension, content);
+        let summary = summarize(&text, title.as_deref());
+        let terms = document_terms(path, &text);
+
+        let id = inner.next_id;
+        inner.next_id += 1;
+        inner.ids.insert(path.to_string(), id);
+
+        for (position, term) in &terms {
+            inner
+                .postings
+                .entry(term.clone())
+                .or_default()
+                .entry(id)
+                .or_default()
+                .push(*position);
+        }
+
+        inner.total_length += terms.len();
+        inner.entries.insert(
+            id,
+            Entry {
+                document: Document {
+                    path: path.to_string(),
+                    domain,
+                    extension,
+                    size: content.len() as u64,
+                    created,
+                    title,
+                    summary,
+                },
+                text,
+                length: terms.len(),
+            },
+        );
+    }
+
+    /// Re-reads a file from the store after it was committed or rolled back.
+    pub async fn refresh(&self, store: &Store, rel: &Path) {
+        let path = rel.to_string_lossy();
+
+        match tokio::fs::read(store.path(rel))
+            .await
+            .map(String::from_utf8)
+        {
+            Ok(Ok(content)) => self.update(&path, &content, SystemTime::now()),
+            _ => self.inner.write().unwrap().remove(&path),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.inner.read().unwrap().entries.len()
+    }
+
+    /// Every document, newest first.
+    pub fn documents(&self) -> Vec<Document> {
+        let inner = self.inner.read().unwrap();
+        let mut documents: Vec<Document> = inner
+            .entries
+            .values()
+            .map(|entry| entry.document.clone())
+            .collect();
+
+        documents.sort_by(|a, b| b.created.cmp(&a.created).then_with(|| a.path.cmp(&b.path)));
+        documents
+    }
+
+    /// Ranked matches, best first. A query with only filters lists matching documents newest
+    /// first, without snippets.
+    pub fn search(&self, query: &Query) -> Vec<Hit> {
+        let inner = self.inner.read().unwrap();
+
+        let mut all_terms: Vec<&String> = query
+            .terms
+            .iter()
+            .chain(query.phrases.iter().flatten())
+            .collect();
+        all_terms.sort();
+        all_terms.dedup();
+
+        let candidates: HashSet<u32> = if all_terms.is_empty() {
+            inner.entries.keys().copied().collect()
+        } else {
+            all_terms
+                .iter()
+                .filter_map(|term| inner.postings.get(*term))
+                .flat_map(|docs| docs.keys().copied())
+                .collect()
+        };
+
+        let average_length = inner.total_length as f64 / inner.entries.len().max(1) as f64;
+        let document_count = inner.entries.len() as f64;
+
+        let mut hits: Vec<Hit> = candidates
+            .into_iter()
+            .filter_map(|id| {
+                let entry = &inner.entries[&id];
+                let document = &entry.document;
+
+                if query
+                    .domain
+                    .as_ref()
+                    .is_some_and(|d| !document.domain.eq_ignore_ascii_case(d))
+                {
+                    return None;
+                }
+
+                if query
+                    .extension
+                    .as_ref()
+                    .is_some_and(|e| !document.extension.eq_ignore_ascii_case(e))
+                {
+                    return None;
+                }
+
+                if !query
+                    .phrases
+                    .iter()
+                    .all(|phrase| inner.contains_phrase(id, phrase))
+                {
+                    return None;
+                }
+
+                let score = all_terms
+                    .iter()
+                    .filter_map(|term| {
+                        let docs = inner.postings.get(*term)?;
+                        let frequency = docs.get(&id)?.len() as f64;
+                        let idf = (1.0
+                            + (document_count - docs.len() as f64 + 0.5)
+                                / (docs.len() as f64 + 0.5))
+                            .ln();
+                        let norm = K1 * (1.0 - B + B * entry.length as f64 / average_length);
+                        Some(idf * frequency * (K1 + 1.0) / (frequency + norm))
+                    })
+                    .sum();
+
+                Some(Hit {
+                    document: document.clone(),
+                    score,
+                    // Listing by filters alone has nothing to highlight
+                    snippets: match all_terms.is_empty() {
+                        true => Vec::new(),
+                        false => snippets(&entry.text, query),
+                    },
+                })
+            })
+            .collect();
+
+        hits.sort_by(|a, b| {
+            b.score
+                .total_cmp(&a.score)
+                .then_with(|| b.document.created.cmp(&a.document.created))
+                .then_with(|| a.document.path.cmp(&b.document.path))
+        });
+
+        hits
+    }
+}
+
+impl Inner {
+    fn remove(&mut self, path: &str) {
+        let Some(id) = self.ids.remove(path) else {
+            return;
+        };
+        let Some(entry) = self.entries.remove(&id) else {
+            return;
+        };
+
+        self.total_length -= entry.length;
+
+        for (_, term) in document_terms(path, &entry.text) {
+            if let Some(docs) = self.postings.get_mut(&term) {
+                docs.remove(&id);
+                if docs.is_empty() {
+                    self.postings.remove(&term);
+                }
+            }
+        }
+    }
+
+    fn contains_phrase(&self, id: u32, phrase: &[String]) -> bool {
+        let mut positions = phrase
+            .iter()
+            .map(|term| self.postings.get(term).and_then(|docs| docs.get(&id)));
+
+        let Some(Some(first)) = positions.next() else {
+            return false;
+        };
+        let rest: Option<Vec<&Vec<u32>>> = positions.collect();
+        let Some(rest) = rest else { return false };
+
+        first.iter().any(|&start| {
+            rest.iter().enumerate().all(|(offset, positions)| {
+                positions
+                    .binary_search(&(start + offset as u32 + 1))
+                    .is_ok()
+            })
+        })
+    }
+}
+
+impl Query {
+    pub fn parse(query: &str) -> Self {
+        let mut parsed = Self::default();
+        let mut rest = query;
+
+        while let Some(start) = rest.find('"') {
+            let (before, after) = rest.split_at(start);
+            parsed.words(before);
+
+            let after = &after[1..];
+            let (phrase, remainder) = after.split_once('"').unwrap_or((after, ""));
+            let phrase: Vec<String> = terms(phrase).collect();
+
+            match phrase.len() {
+                0 => {}
+                1 => parsed.terms.extend(phrase),
+                _ => parsed.phrases.push(phrase),
+            }
+
+            rest = remainder;
+        }
+
+        parsed.words(rest);
+        parsed
+    }
+
+    fn words(&mut self, words: &str) {
+        for word in words.split_whitespace() {
+            if let Some(domain) = word
+                .strip_prefix("domain:")
+                .or_else(|| word.strip_prefix("site:"))
+            {
+                self.domain = Some(domain.to_lowercase());
+            } else if let Some(extension) = word.strip_prefix("ext:") {
+                self.extension = Some(extension.trim_start_matches('.').to_lowercase());
+            } else {
+                self.terms.extend(terms(word));
+            }
+        }
+    }
+
+    /// Restricts to one domain, overriding a `domain:` in the query.
+    pub fn with_domain(mut self, domain: Option<String>) -> Self {
+        self.domain = domain.map(|d| d.to_lowercase()).or(self.domain);
+        self
+    }
+
+    /// Restricts to one extension, overriding an `ext:` in the query.
+    pub fn with_extension(mut self, extension: Option<String>) -> Self {
+        self.extension = extension
+            .map(|e| e.trim_start_matches('.').to_lowercase())
+            .or(self.extension);
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.terms.is_empty()
+            && self.phrases.is_empty()
+            && self.domain.is_none()
+            && self.extension.is_none()
+    }
+}
+
+impl Snippet {
+    /// Escaped HTML with the matches wrapped in `<mark>`.
+    pub fn to_html(&self) -> String {
+        let mut html = String::new();
+        let mut last = 0;
+
+        for &(start, end) in &self.highlights {
+            html.push_str(&html_escape::encode_text(&self.text[last..start]));
+            html.push_str("<mark>");
+            html.push_str(&html_escape::encode_text(&self.text[start..end]));
+            html.push_str("</mark>");
+            last = end;
+        }
+
+        html.push_str(&html_escape::encode_text(&self.text[last..]));
+        html
+    }
+}
+
+fn split_path(path: &str) -> (String, String) {
+    let domain = path.split('/').next().unwrap_or_default().to_lowercase();
+    let extension = Path::new(path)
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+
+    (domain, extension)
+}
+
+/// Byte spans of the alphanumeric runs in `text`.
+fn spans(text: &str) -> impl Iterator<Item = (usize, usize)> + '_ {
+    let mut start = None;
+
+    text.char_indices()
+        .chain(std::iter::once((text.len(), ' ')))
+        .filter_map(move |(i, c)| match (c.is_alphanumeric(), start) {
+            (true, None) => {
+                start = Some(i);
+                None
+            }
+            (false, Some(s)) => {
+                start = None;
+                Some((s, i))
+            }
+            _ => None,
+        })
+}
+
+fn terms(text: &str) -> impl Iterator<Item = String> + '_ {
+    spans(text).map(|(start, end)| text[start..end].to_lowercase())
+}
+
+/// The terms of a document with their positions. The text starts one position after the path,
+/// so no phrase spans both.
+fn document_terms(path: &str, text: &str) -> Vec<(u32, String)> {
+    let path: Vec<String> = terms(path).collect();
+    let text_start = path.len() as u32 + 1;
+
+    path.into_iter()
+        .zip(0..)
+        .chain(terms(text).zip(text_start..))
+        .map(|(term, position)| (position, term))
+        .collect()
+}
+
+/// A one-line description of a file: its title, if it has one, and the start of its text.
+pub fn describe(extension: &str, content: &str) -> String {
+    let text = extract_text(extension, content);
+    let title = extract_title(extension, content);
+    let summary = summarize(&text, title.as_deref())
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    match title {
+        Some(title) if summary.is_empty() => title,
+        Some(title) => format!("{title}: {summary}"),
+        None => summary,
+    }
+}
+
+fn is_markup(extension: &str) -> bool {
+    matches!(extension, "html" | "htm" | "svg" | "xml" | "xhtml")
+}
+
+fn extract_title(extension: &str, content: &str) -> Option<String> {
+    if !is_markup(extension) {
+        return None;
+    }
+
+    let lower = content.to_ascii_lowercase();
+    let open = lower.find("<title")?;
+    let start = open + lower[open..].find('>')? + 1;
+    let end = start + lower[start..].find("</title")?;
+
+    let title = html_escape::decode_html_entities(&content[start..end])
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    (!title.is_empty()).then_some(title)
+}
+
+fn summarize(text: &str, title: Option<&str>) -> String {
+    let text = title
+        .and_then(|title| text.strip_prefix(title))
+        .unwrap_or(text)
+        .trim_start();
+
+    match text.char_indices().nth(SUMMARY_LENGTH) {
+        None => text.to_string(),
+        Some((end, _)) => {
+            // Cut at a word boundary where there is one
+            let end = text[..end].rfind(' ').unwrap_or(end);
+            format!("{}…", &text[..end])
+        }
+    }
+}
+
+/// Plain text of a file. Markup is dropped, along with scripts and styles which are noise.
+fn extract_text(extension: &str, content: &str) -> String {
+    if !is_markup(extension) {
+        return content.to_string();
+    }
+
+    let mut text = String::with_capacity(content.len());
+    let lower = content.to_ascii_lowercase();
+    let mut i = 0;
+
+    while let Some(offset) = content[i..].find('<') {
+        text.push_str(&content[i..i + offset]);
+        text.push(' ');
+        i += offset;
+
+        let skip_until = ["script", "style"]
+            .into_iter()
+            .find(|tag| lower[i + 1..].starts_with(tag))
+            .map(|tag| format!("</{tag}"));
+
+        let end = match skip_until.and_then(|close| lower[i..].find(&close).map(|at| i + at)) {
+            Some(close) => lower[close..].find('>').map(|at| close + at),
+            None => content[i..].find('>').map(|at| i + at),
+        };
+
+        match end {
+            Some(end) => i = end + 1,
+            None => {
+                i = content.len();
+                break;
+            }
+        }
+    }
+
+    text.push_str(&content[i..]);
+
+    html_escape::decode_html_entities(&text)
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Windows of text around the first few matches, not overlapping.
+fn snippets(text: &str, query: &Query) -> Vec<Snippet> {
+    let wanted: HashSet<&str> = query
+        .terms
+        .iter()
+        .chain(query.phrases.iter().flatten())
+        .map(String::as_str)
+        .collect();
+
+    let spans: Vec<(usize, usize)> = spans(text).collect();
+    let is_match =
+        |&(start, end): &(usize, usize)| wanted.contains(text[start..end].to_lowercase().as_str());
+
+    let mut snippets = Vec::new();
+    let mut next = 0;
+
+    while snippets.len() < MAX_SNIPPETS
+        && let Some(offset) = spans[next..].iter().position(is_match)
+    {
+        let first = next + offset;
+        let from = first.saturating_sub(SNIPPET_RADIUS).max(next);
+        let to = (first + SNIPPET_RADIUS).min(spans.len() - 1);
+
+        let start = spans[from].0;
+        let end = spans[to].1;
+
+        snippets.push(Snippet {
+            text: text[start..end].to_string(),
+            highlights: spans[from..=to]
+                .iter()
+                .filter(|span| is_match(span))
+                .map(|&(s, e)| (s - start, e - start))
+                .collect(),
+        });
+
+        next = to + 1;
+    }
+
+    snippets
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn paths(index: &SearchIndex, query: &str) -> Vec<String> {
+        let mut paths: Vec<String> = index
+            .search(&Query::parse(query))
+            .into_iter()
+            .map(|hit| hit.document.path)
+            .collect();
+        paths.sort();
+        paths
+    }
+
+    #[test]
+    fn phrases_stay_within_the_path_or_the_text() {
+        let index = SearchIndex::default();
+        let now = SystemTime::now();
+        index.update("example.com/news.html", "<p>Rust weekly</p>", now);
+        index.update("example.com/about.html", "<p>about rust weekly</p>", now);
+
+        // `html` ends the path and `rust` starts the text of the first page
+        assert_eq!(paths(&index, "\"html rust\""), Vec::<String>::new());
+        assert_eq!(
+            paths(&index, "\"rust weekly\""),
+            ["example.com/about.html", "example.com/news.html"]
+        );
+        assert_eq!(paths(&index, "\"news html\""), ["example.com/news.html"]);
+
+        // Removing a document takes every position with it
+        index.update("example.com/news.html", "<p>Nothing here</p>", now);
+        assert_eq!(paths(&index, "\"rust weekly\""), ["example.com/about.html"]);
+    }
+
+    fn ranked(index: &SearchIndex, query: &str) -> Vec<String> {
+        index
+            .search(&Query::parse(query))
+            .into_iter()
+            .map(|hit| hit.document.path)
+            .collect()
+    }
+
+    #[test]
+    fn multi_term_queries_rank_by_relevance() {
+        let index = SearchIndex::default();
+        let now = SystemTime::now();
+        index.update(
+            "a.com/one.html",
+            "<p>rust rust rust and some gardening</p>",
+            now,
+        );
+        index.update("a.com/two.html", "<p>rust cooking recipes</p>", now);
+        index.update("a.com/three.html", "<p>cooking cooking pasta</p>", now);
+        index.update("a.com/four.html", "<p>gardening</p>", now);
+
+        // Matching both terms beats repeating one, rare terms weigh more than common ones
+        let hits = ranked(&index, "rust cooking");
+        assert_eq!(hits[0], "a.com/two.html");
+        assert_eq!(hits.len(), 3);
+        assert!(!hits.contains(&"a.com/four.html".to_string()));
+
+        let hits = index.search(&Query::parse("rust"));
+        assert_eq!(hits[0].document.path, "a.com/one.html");
+        assert!(hits[0].score > hits[1].score);
+
+        // Equal scores fall back to the path
+        let index = SearchIndex::default();
+        index.update("b.com/b.txt", "same", now);
+        index.update("b.com/a.txt", "same", now);
+        assert_eq!(ranked(&index, "same"), ["b.com/a.txt", "b.com/b.txt"]);
+    }
+
+    #[test]
+    fn filters_restrict_domains_and_extensions() {
+        let index = SearchIndex::default();
+        let now = SystemTime::now();
+        index.update("a.com/index.html", "<p>hello</p>", now);
+        index.update("a.com/style.css", "/* hello */", now);
+        index.update("b.com/index.html", "<p>hello</p>", now);
+
+        assert_eq!(
+            paths(&index, "hello domain:a.com"),
+            ["a.com/index.html", "a.com/style.css"]
+        );
+        assert_eq!(paths(&index, "hello site:B.com"), ["b.com/index.html"]);
+        assert_eq!(paths(&index, "ext:.CSS"), ["a.com/style.css"]);
+        assert_eq!(
+            paths(&index, "hello domain:a.com ext:html"),
+            ["a.com/index.html"]
+        );
+        assert_eq!(paths(&index, "domain:c.com"), Vec::<String>::new());
+
+        // Set from outside the query, it wins over the query's own filter
+        let query = Query::parse("hello domain:a.com")
+            .with_domain(Some("B.com".to_string()))
+            .with_extension(None);
+        let hits: Vec<String> = index
+            .search(&query)
+            .into_iter()
+            .map(|hit| hit.document.path)
+            .collect();
+        assert_eq!(hits, ["b.com/index.html"]);
+
+        // Filters alone list without snippets
+        assert!(
+            index
+                .search(&Query::parse("domain:a.com"))
+                .iter()
+                .all(|hit| hit.snippets.is_empty() && hit.score == 0.0)
+        );
+    }
+
+    #[test]
+    fn queries_are_parsed() {
+        let query = Query::parse(r#"Hello, "Exact  Phrase" "single" ext:.HTML site:Example.com"#);
+        assert_eq!(query.terms, ["hello", "single"]);
+        assert_eq!(query.phrases, [["exact", "phrase"]]);
+        assert_eq!(query.domain.as_deref(), Some("example.com"));
+        assert_eq!(query.extension.as_deref(), Some("html"));
+
+        // An unterminated quote runs to the end
+        let query = Query::parse(r#"a "b c"#);
+        assert_eq!(query.terms, ["a"]);
+        assert_eq!(query.phrases, [["b", "c"]]);
+
+        // Punctuation splits words, empty quotes and filters without a word are ignored
+        let query = Query::parse(r#"don't "" "!""#);
+        assert_eq!(query.terms, ["don", "t"]);
+        assert!(query.phrases.is_empty());
+
+        assert!(Query::parse("").is_empty());
+        assert!(Query::parse(r#"  "" ,. "#).is_empty());
+        assert!(!Query::parse("ext:css").is_empty());
+    }
+
+    #[test]
+    fn snippets_highlight_matches() {
+        let index = SearchIndex::default();
+        let words: Vec<String> = (0..100).map(|i| format!("w{i}")).collect();
+        let text = format!(
+            "Über {} rust {} RUST end",
+            words[..40].join(" "),
+            words[40..].join(" ")
+        );
+        index.update("a.com/a.txt", &text, SystemTime::now());
+
+        let hits = index.search(&Query::parse("rust über"));
+        let snippets = &hits[0].snippets;
+        assert_eq!(snippets.len(), 3);
+
+        for snippet in snippets {
+            assert!(!snippet.highlights.is_empty());
+            for &(start, end) in &snippet.highlights {
+                let matched = snippet.text[start..end].to_lowercase();
+                assert!(matched == "rust" || matched == "über", "{matched}");
+            }
+        }
+
+        // Within the radius of the first match, not overlapping the next
+        assert!(snippets[0].text.starts_with("Über"));
+        assert!(snippets[0].text.ends_with("w15"));
+        assert_eq!(snippets[1].highlights.len(), 1);
+        assert!(snippets[2].text.ends_with("RUST end"));
+
+        let snippet = Snippet {
+            text: "a <b> rust".to_string(),
+            highlights: vec![(6, 10)],
+        };
+        assert_eq!(snippet.to_html(), "a &lt;b&gt; <mark>rust</mark>");
+    }
+
+    #[test]
+    fn updates_replace_postings() {
+        let index = SearchIndex::default();
+        let now = SystemTime::now();
+        index.update("a.com/a.txt", "shared only_a only_a", now);
+        index.update("a.com/b.txt", "shared", now);
+
+        let frequency = |term: &str| {
+            let inner = index.inner.read().unwrap();
+            inner.postings.get(term).map_or(0, |docs| docs.len())
+        };
+        assert_eq!(frequency("shared"), 2);
+        assert_eq!(frequency("only_a"), 0);
+        assert_eq!(frequency("only"), 1);
+        assert_eq!(frequency("txt"), 2);
+
+        // The old terms leave with the old content
+        index.update("a.com/a.txt", "replaced", now);
+        assert_eq!(index.len(), 2);
+        assert_eq!(frequency("shared"), 1);
+        assert_eq!(frequency("only"), 0);
+        assert_eq!(frequency("replaced"), 1);
+        assert_eq!(paths(&index, "shared"), ["a.com/b.txt"]);
+
+        {
+            let inner = index.inner.read().unwrap();
+            let lengths: usize = inner.entries.values().map(|entry| entry.length).sum();
+            assert_eq!(inner.total_length, lengths);
+            assert_eq!(inner.ids.len(), inner.entries.len());
+        }
+
+        index.inner.write().unwrap().remove("a.com/a.txt");
+        index.inner.write().unwrap().remove("a.com/b.txt");
+        let inner = index.inner.read().unwrap();
+        assert!(inner.entries.is_empty() && inner.ids.is_empty() && inner.postings.is_empty());
+        assert_eq!(inner.total_length, 0);
+    }
+}
@@ -0,0 +1,305 @@
+//! Server-Sent Events decoder, following the WHATWG event stream format.
+//!
+//! Handles `\n`, `\r\n` and lone `\r` line endings split anywhere across chunks, multi-line
+//! `data:` fields, `event:`/`id:`/`retry:` fields and `:` comments.
+use async_stream::try_stream;
+use futures_util::stream::{BoxStream, Stream, StreamExt};
+use std::io;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SseEvent {
+    /// `None` means the default `message` type.
+    pub event: Option<String>,
+    pub data: String,
+    pub id: Option<String>,
+    pub retry: Option<u64>,
+}
+
+#[derive(Default)]
+pub struct SseDecoder {
+    buffer: Vec<u8>,
+    /// The last chunk ended in `\r`, so a leading `\n` in the next one belongs to it.
+    pending_cr: bool,
+    seen_bom: bool,
+    event: Option<String>,
+    data: Option<String>,
+    id: Option<String>,
+    retry: Option<u64>,
+}
+
+impl SseDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Feeds raw bytes, returning every event completed by them.
+    pub fn feed(&mut self, chunk: &[u8]) -> Vec<SseEvent> {
+        let mut chunk = chunk;
+
+        if self.pending_cr {
+            self.pending_cr = false;
+            if let Some(rest) = chunk.strip_prefix(b"\n") {
+                chunk = rest;
+            }
+        }
+
+        self.buffer.extend_from_slice(chunk);
+
+        if !self.seen_bom && self.buffer.len() >= 3 {
+            self.seen_bom = true;
+            if self.buffer.starts_with(b"\xEF\xBB\xBF") {
+                self.buffer.drain(..3);
+            }
+        }
+
+        let mut events = Vec::new();
+
+        while let Some(end) = self.buffer.iter().position(|&b| b == b'\n' || b == b'\r') {
+            let line = String::from_utf8_lossy(&self.buffer[..end]).into_owned();
+
+            let terminator = match self.buffer.get(end..end + 2) {
+                Some(b"\r\n") => 2,
+                // A `\r` at the very end may still be followed by a `\n` in the next chunk
+                None if self.buffer[end] == b'\r' => {
+                    self.pending_cr = true;
+                    1
+                }
+                _ => 1,
+            };
+            self.buffer.drain(..end + terminator);
+
+            if let Some(event) = self.line(&line) {
+                events.push(event);
+            }
+        }
+
+        events
+    }
+
+    fn line(&mut self, line: &str) -> Option<SseEvent> {
+        if line.is_empty() {
+            return self.dispatch();
+        }
+
+        // Comment, used for keep-alives
+        if line.starts_with(':') {
+            return None;
+        }
+
+        let (field, value) = match line.split_once(':') {
+            Some((field, value)) => (field, value.strip_prefix(' ').unwrap_or(value)),
+            None => (line, ""),
+        };
+
+        match field {
+            "event" => self.event = Some(value.to_string()),
+            "data" => match &mut self.data {
+                Some(data) => {
+                    data.push('\n');
+                    data.push_str(value);
+                }
+                None => self.data = Some(value.to_string()),
+            },
+            "id" if !value.contains('\0') => self.id = Some(value.to_string()),
+            "retry" => {
+                if let Ok(retry) = value.parse() {
+                    self.retry = Some(retry);
+                }
+            }
+            _ => {}
+        }
+
+        None
+    }
+
+    fn dispatch(&mut self) -> Option<SseEvent> {
+        let event = self.event.take();
+        let retry = self.retry.take();
+
+        // Events without data are never dispatched, but still reset the event type
+        let data = self.data.take()?;
+
+        Some(SseEvent {
+            event,
+            data,
+            id: self.id.clone(),
+            retry,
+        })
+    }
+}
+
+/// Decodes a byte stream into events. A trailing event without its blank line is discarded, as
+/// the spec requires.
+pub fn decode<S, B>(body: S) -> BoxStream<'static, io::Result<SseEvent>>
+where
+    S: Stream<Item = io::Result<B>> + Send + 'static,
+    B: AsRef<[u8]> + Send,
+{
+    Box::pin(try_stream! {
+        let mut body = Box::pin(body);
+        let mut decoder = SseDecoder::new();
+
+        while let Some(chunk) = body.next().await {
+            for event in decoder.feed(chunk?.as_ref()) {
+                yield event;
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Feeds `chunks` one after another, collecting every event.
+    fn events(chunks: &[&[u8]]) -> Vec<SseEvent> {
+        let mut decoder = SseDecoder::new();
+        chunks
+            .iter()
+            .flat_map(|chunk| decoder.feed(chunk))
+            .collect()
+    }
+
+    fn data(chunks: &[&[u8]]) -> Vec<String> {
+        events(chunks).into_iter().map(|event| event.data).collect()
+    }
+
+    /// Every way of splitting `stream` in two.
+    fn splits(stream: &[u8]) -> impl Iterator<Item = (&[u8], &[u8])> {
+        (0..=stream.len()).map(|i| stream.split_at(i))
+    }
+
+    #[test]
+    fn line_endings_are_equivalent() {
+        for stream in [
+            &b"data: a\n\ndata: b\n\n"[..],
+            b"data: a\r\n\r\ndata: b\r\n\r\n",
+            b"data: a\r\rdata: b\r\r",
+            b"data: a\r\n\ndata: b\r\r\n",
+        ] {
+            assert_eq!(data(&[stream]), ["a", "b"], "{stream:?}");
+        }
+    }
+
+    #[test]
+    fn line_endings_split_across_chunks() {
+        for stream in [
+            &b"data: a\n\ndata: b\n\n"[..],
+            b"data: a\r\n\r\ndata: b\r\n\r\n",
+            b"data: a\r\rdata: b\r\r",
+        ] {
+            for (first, second) in splits(stream) {
+                assert_eq!(data(&[first, second]), ["a", "b"], "{first:?} {second:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn crlf_split_between_chunks_is_one_line_ending() {
+        // Were the `\n` a line of its own, the event would be dispatched early and `b` lost
+        assert_eq!(data(&[b"data: a\r", b"\ndata: b\n\n"]), ["a\nb"]);
+        assert_eq!(data(&[b"data: a\r\n\r", b"\n", b"data: b\n\n"]), ["a", "b"]);
+    }
+
+    #[test]
+    fn byte_by_byte() {
+        let stream = "event: delta\r\ndata: {\"x\": \"é🦀\"}\r\n\r\n: ping\n\ndata: [DONE]\n\n";
+        let mut decoder = SseDecoder::new();
+        let events: Vec<_> = stream
+            .as_bytes()
+            .iter()
+            .flat_map(|byte| decoder.feed(&[*byte]))
+            .collect();
+
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].event.as_deref(), Some("delta"));
+        assert_eq!(events[0].data, "{\"x\": \"é🦀\"}");
+        assert_eq!(events[1].data, "[DONE]");
+    }
+
+    #[test]
+    fn multi_line_data_is_joined() {
+        assert_eq!(
+            data(&[b"data: first\ndata: second\ndata\ndata:third\n\n"]),
+            ["first\nsecond\n\nthird"]
+        );
+    }
+
+    #[test]
+    fn only_one_leading_space_is_stripped() {
+        assert_eq!(data(&[b"data:  indented\n\n"]), [" indented"]);
+    }
+
+    #[test]
+    fn fields_are_collected() {
+        let events = events(&[b"event: error\nid: 7\nretry: 1000\ndata: x\n\ndata: y\n\n"]);
+
+        assert_eq!(
+            events[0],
+            SseEvent {
+                event: Some("error".into()),
+                data: "x".into(),
+                id: Some("7".into()),
+                retry: Some(1000),
+            }
+        );
+        // The type resets after every event, the last id doesn't
+        assert_eq!(events[1].event, None);
+        assert_eq!(events[1].id.as_deref(), Some("7"));
+        assert_eq!(events[1].retry, None);
+    }
+
+    #[test]
+    fn comments_and_events_without_data_are_skipped() {
+        assert_eq!(
+            data(&[b": keep-alive\n\nevent: ping\n\nretry: 5\n\ndata: a\n\n"]),
+            ["a"]
+        );
+        assert_eq!(events(&[b"event: ping\n\ndata: a\n\n"])[0].event, None);
+    }
+
+    #[test]
+    fn bom_is_stripped() {
+        let stream = b"\xEF\xBB\xBFdata: a\n\n";
+        for (first, second) in splits(stream) {
+            assert_eq!(data(&[first, second]), ["a"], "{first:?} {second:?}");
+        }
+        assert_eq!(data(&[b"\xEF", b"\xBB", b"\xBF", b"data: a\n\n"]), ["a"]);
+
+        // Only at the very start of the stream
+        let events = events(&[b"data: a\n\n\xEF\xBB\xBFdata: b\n\n"]);
+        assert_eq!(events.len(), 1);
+    }
+
+    #[test]
+    fn utf8_split_mid_codepoint() {
+        let stream = "data: héllo 🦀\n\n".as_bytes();
+        for (first, second) in splits(stream) {
+            assert_eq!(data(&[first, second]), ["héllo 🦀"], "{first:?} {second:?}");
+        }
+    }
+
+    #[test]
+    fn incomplete_trailing_event_is_discarded() {
+        assert_eq!(data(&[b"data: a\n\ndata: b\n"]), ["a"]);
+        assert_eq!(data(&[b"data: a\n\ndata: b"]), ["a"]);
+    }
+
+    #[tokio::test]
+    async fn decode_stream() {
+        let chunks: Vec<io::Result<&[u8]>> = vec![Ok(b"data: a\r"), Ok(b"\n\r\ndata: b\n\n")];
+        let events: Vec<_> = decode(futures_util::stream::iter(chunks))
+            .map(|event| event.unwrap().data)
+            .collect()
+            .await;
+        assert_eq!(events, ["a", "b"]);
+
+        let chunks: Vec<io::Result<&[u8]>> = vec![
+            Ok(b"data: a\n\n"),
+            Err(io::Error::other("connection reset")),
+        ];
+        let mut events = decode(futures_util::stream::iter(chunks));
+        assert_eq!(events.next().await.unwrap().unwrap().data, "a");
+        assert!(events.next().await.unwrap().is_err());
+    }
+}
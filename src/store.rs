@@ -0,0 +1,454 @@
+//! Persistence of generated files.
+//!
+//! Output is streamed into a hidden temporary file next to its final location and only renamed
+//! into place once the generation is known to be complete, so `ServeDir` never sees a truncated
+//! file. Requests for hidden files are refused before they reach it. Renaming within one
+//! directory is atomic and never crosses filesystems.
+//!
+//! Every committed file is also recorded in a history directory as a numbered version, so pages
+//! can be regenerated without losing earlier takes and rolled back later. Each version has a
+//! `<N>.json` [`Metadata`] sidecar next to it.
+//!
+//! Output a profile's checks rejected is recorded with the reason, so the path isn't sent to the
+//! model again on every request. Committing a new version clears the record.
+use axum::http::StatusCode;
+use jwalk::WalkDir;
+use mime_guess::Mime;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use time::OffsetDateTime;
+use tokio::fs::{self, File};
+use tokio::io::{self, AsyncWriteExt, BufWriter};
+
+use crate::canonical;
+use crate::meta::Metadata;
+
+static TEMP_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// What happens to the output of a generation that did not complete.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PartialOutput {
+    Discard,
+    /// Moved into the quarantine directory for inspection.
+    Quarantine,
+}
+
+impl FromStr for PartialOutput {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, String> {
+        match value {
+            "discard" => Ok(Self::Discard),
+            "quarantine" => Ok(Self::Quarantine),
+            other => Err(format!(
+                "unknown partial output `{other}`, expected one of: discard, quarantine"
+            )),
+        }
+    }
+}
+
+/// Marks which version is live, since a rollback makes it differ from the latest one.
+const CURRENT: &str = "current";
+
+#[derive(Debug, Clone)]
+pub struct Store {
+    root: PathBuf,
+    quarantine: PathBuf,
+    history: PathBuf,
+    rejected: PathBuf,
+    partial: PartialOutput,
+    max_path_length: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct Version {
+    pub number: u32,
+    pub size: u64,
+    pub created: OffsetDateTime,
+}
+
+impl Store {
+    /// `root` is the served data directory, `state` holds quarantine, history and rejections.
+    pub fn new(
+        root: impl Into<PathBuf>,
+        state: impl AsRef<Path>,
+        partial: PartialOutput,
+        max_path_length: usize,
+    ) -> Self {
+        let state = state.as_ref();
+
+        Self {
+            root: root.into(),
+            quarantine: state.join("quarantine"),
+            history: state.join("history"),
+            rejected: state.join("rejected"),
+            partial,
+            max_path_length,
+        }
+    }
+
+    /// Maps a request path onto the file it is stored at, relative to the data directory.
+    pub fn resolve(&self, path: &str) -> Result<(PathBuf, Mime), StatusCode> {
+        canonical::storage_path(path, self.max_path_length)
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Where a path relative to the data directory lives on disk.
+    pub fn path(&self, rel: impl AsRef<Path>) -> PathBuf {
+        self.root.join(rel)
+    }
+
+    /// Starts writing `rel`. Nothing is visible at the final path until [`PendingFile::commit`].
+    pub async fn create(&self, rel: impl AsRef<Path>) -> io::Result<PendingFile> {
+        let rel = rel.as_ref();
+        let target = self.path(rel);
+        let parent = target
+            .parent()
+            .ok_or_else(|| io::Error::other("cannot store a file without a parent"))?;
+
+        fs::create_dir_all(parent).await?;
+
+        let name = target
+            .file_name()
+            .ok_or_else(|| io::Error::other("cannot store a file without a name"))?
+            .to_string_lossy();
+
+        let temp = parent.join(format!(
+            ".{name}.{}-{}.tmp",
+            std::process::id(),
+            TEMP_COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+
+        let file = File::create(&temp).await?;
+
+        Ok(PendingFile {
+            writer: Some(BufWriter::new(file)),
+            temp,
+            target,
+            history: self.history.join(rel),
+            rejection: self.rejected.join(rel),
+            quarantine: match self.partial {
+                PartialOutput::Discard => None,
+                PartialOutput::Quarantine => Some(self.quarantine.join(rel)),
+            },
+        })
+    }
+
+    /// All recorded versions of `rel`, oldest first.
+    pub async fn versions(&self, rel: impl AsRef<Path>) -> io::Result<Vec<Version>> {
+        let dir = self.history.join(rel);
+        let mut versions = Vec::new();
+
+        for number in version_numbers(&dir).await? {
+            let metadata = fs::metadata(dir.join(number.to_string())).await?;
+            versions.push(Version {
+                number,
+                size: metadata.len(),
+                created: metadata.modified()?.into(),
+            });
+        }
+
+        Ok(versions)
+    }
+
+    /// The version currently served, if the file has any history.
+    pub async fn current_version(&self, rel: impl AsRef<Path>) -> Option<u32> {
+        let current = fs::read_to_string(self.history.join(rel).join(CURRENT))
+            .await
+            .ok()?;
+        current.trim().parse().ok()
+    }
+
+    pub async fn read_version(&self, rel: impl AsRef<Path>, number: u32) -> io::Result<Vec<u8>> {
+        fs::read(self.history.join(rel).join(number.to_string())).await
+    }
+
+    pub async fn metadata(&self, rel: impl AsRef<Path>, number: u32) -> Option<Metadata> {
+        let json = fs::read(self.history.join(rel).join(format!("{number}.json")))
+            .await
+            .ok()?;
+        serde_json::from_slice(&json).ok()
+    }
+
+    /// Metadata of the version currently served.
+    pub async fn current_metadata(&self, rel: impl AsRef<Path>) -> Option<Metadata> {
+        let rel = rel.as_ref();
+        self.metadata(rel, self.current_version(rel).await?).await
+    }
+
+    /// Why the last output generated for `rel` was rejected, if it was and nothing was committed
+    /// since.
+    pub async fn rejection(&self, rel: impl AsRef<Path>) -> Option<String> {
+        fs::read_to_string(self.rejected.join(rel)).await.ok()
+    }
+
+    /// Makes an earlier version live again. The history itself is left untouched.
+    pub async fn rollback(&self, rel: impl AsRef<Path>, number: u32) -> io::Result<()> {
+        let rel = rel.as_ref();
+        let content = self.read_version(rel, number).await?;
+
+        let mut file = self.create(rel).await?;
+        file.writer
+            .as_mut()
+            .expect("freshly created")
+            .write_all(&content)
+            .await?;
+        file.close().await?;
+
+        fs::rename(&file.temp, &file.target).await?;
+        file.temp = PathBuf::new();
+
+        fs::write(file.history.join(CURRENT), number.to_string()).await
+    }
+}
+
+impl Store {
+    /// Keeps the newest `keep` versions of every file, plus the live one. Returns what was
+    /// removed, or with `dry_run` what would be.
+    pub async fn trim_history(&self, keep: usize, dry_run: bool) -> io::Result<Vec<PathBuf>> {
+        let directories: Vec<PathBuf> = WalkDir::new(&self.history)
+            .skip_hidden(false)
+            .into_iter()
+            .filter_map(Result::ok)
+            .filter(|entry| entry.file_name() == CURRENT)
+            .filter_map(|entry| Some(entry.path().parent()?.to_path_buf()))
+            .collect();
+
+        let mut removed = Vec::new();
+
+        for dir in directories {
+            let current = fs::read_to_string(dir.join(CURRENT)).await?;
+            let current: Option<u32> = current.trim().parse().ok();
+
+            let numbers = version_numbers(&dir).await?;
+            let stale = numbers.len().saturating_sub(keep);
+
+            for number in numbers.into_iter().take(stale) {
+                if Some(number) == current {
+                    continue;
+                }
+
+                for path in [
+                    dir.join(number.to_string()),
+                    dir.join(format!("{number}.json")),
+                ] {
+                    if fs::try_exists(&path).await? {
+                        if !dry_run {
+                            fs::remove_file(&path).await?;
+                        }
+                        removed.push(path);
+                    }
+                }
+            }
+        }
+
+        Ok(removed)
+    }
+
+    /// Deletes everything below `rel`, e.g. a whole domain, including its history and
+    /// quarantined output.
+    pub async fn remove(&self, rel: impl AsRef<Path>, dry_run: bool) -> io::Result<Vec<PathBuf>> {
+        let rel = rel.as_ref();
+        let mut removed = Vec::new();
+
+        for path in [
+            self.path(rel),
+            self.history.join(rel),
+            self.quarantine.join(rel),
+            self.rejected.join(rel),
+        ] {
+            if !fs::try_exists(&path).await? {
+                continue;
+            }
+
+            if !dry_run {
+                match fs::metadata(&path).await?.is_dir() {
+                    true => fs::remove_dir_all(&path).await?,
+                    false => fs::remove_file(&path).await?,
+                }
+            }
+            removed.push(path);
+        }
+
+        Ok(removed)
+    }
+
+    pub async fn clear_quarantine(&self, dry_run: bool) -> io::Result<Vec<PathBuf>> {
+        if !fs::try_exists(&self.quarantine).await? {
+            return Ok(Vec::new());
+        }
+
+        if !dry_run {
+            fs::remove_dir_all(&self.quarantine).await?;
+        }
+        Ok(vec![self.quarantine.clone()])
+    }
+
+    /// Removes temporary files older than `age`, left behind by a process that was killed
+    /// mid-generation. Younger ones may belong to a server that is still running.
+    pub async fn remove_stale_temp_files(
+        &self,
+        age: Duration,
+        dry_run: bool,
+    ) -> io::Result<Vec<PathBuf>> {
+        let temp_files: Vec<PathBuf> = WalkDir::new(&self.root)
+            .skip_hidden(false)
+            .into_iter()
+            .filter_map(Result::ok)
+            .filter(|entry| {
+                let name = entry.file_name().to_string_lossy();
+                entry.file_type().is_file() && name.starts_with('.') && name.ends_with(".tmp")
+            })
+            .map(|entry| entry.path())
+            .collect();
+
+        let mut removed = Vec::new();
+
+        for path in temp_files {
+            let modified = fs::metadata(&path).await?.modified()?;
+            if modified.elapsed().unwrap_or_default() < age {
+                continue;
+            }
+
+            if !dry_run {
+                fs::remove_file(&path).await?;
+            }
+            removed.push(path);
+        }
+
+        Ok(removed)
+    }
+}
+
+async fn version_numbers(dir: &Path) -> io::Result<Vec<u32>> {
+    let mut numbers = Vec::new();
+
+    let mut entries = match fs::read_dir(dir).await {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(numbers),
+        Err(e) => return Err(e),
+    };
+
+    while let Some(entry) = entries.next_entry().await? {
+        if let Some(number) = entry.file_name().to_str().and_then(|n| n.parse().ok()) {
+            numbers.push(number);
+        }
+    }
+
+    numbers.sort_unstable();
+    Ok(numbers)
+}
+
+/// A file being generated. Dropping it without committing removes the temporary file.
+pub struct PendingFile {
+    writer: Option<BufWriter<File>>,
+    temp: PathBuf,
+    target: PathBuf,
+    history: PathBuf,
+    rejection: PathBuf,
+    quarantine: Option<PathBuf>,
+}
+
+impl PendingFile {
+    pub async fn write(&mut self, chunk: &str) -> io::Result<()> {
+        match &mut self.writer {
+            Some(writer) => writer.write_all(chunk.as_bytes()).await,
+            None => Err(io::Error::other("pending file already closed")),
+        }
+    }
+
+    /// Replaces everything written so far. Nothing can be written afterwards.
+    pub async fn rewrite(&mut self, content: &str) -> io::Result<()> {
+        self.close().await?;
+        fs::write(&self.temp, content).await
+    }
+
+    async fn close(&mut self) -> io::Result<()> {
+        if let Some(mut writer) = self.writer.take() {
+            writer.flush().await?;
+            writer.into_inner().sync_all().await?;
+        }
+        Ok(())
+    }
+
+    /// Atomically moves the file into place, replacing any previous version, and records its
+    /// metadata. Returns the new version number.
+    pub async fn commit(mut self, mut metadata: Metadata) -> io::Result<u32> {
+        self.close().await?;
+
+        fs::create_dir_all(&self.history).await?;
+        let mut numbers = version_numbers(&self.history).await?;
+
+        // Files generated before history was kept become version 1
+        if numbers.is_empty() && fs::try_exists(&self.target).await? {
+            fs::copy(&self.target, self.history.join("1")).await?;
+            numbers.push(1);
+        }
+
+        let number = numbers.last().map_or(1, |n| n + 1);
+        fs::copy(&self.temp, self.history.join(number.to_string())).await?;
+
+        metadata.version = number;
+        let json = serde_json::to_vec_pretty(&metadata).map_err(io::Error::other)?;
+        fs::write(self.history.join(format!("{number}.json")), json).await?;
+
+        fs::rename(&self.temp, &self.target).await?;
+        self.temp = PathBuf::new();
+
+        fs::write(self.history.join(CURRENT), number.to_string()).await?;
+
+        match fs::remove_file(&self.rejection).await {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+
+        Ok(number)
+    }
+
+    /// Throws the output away like [`PendingFile::abandon`], recording that it was rejected for
+    /// `reason`.
+    pub async fn reject(self, reason: &str) -> io::Result<()> {
+        if let Some(parent) = self.rejection.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+        fs::write(&self.rejection, reason).await?;
+
+        self.abandon().await
+    }
+
+    /// Throws the output away, or quarantines it if the store is configured to.
+    pub async fn abandon(mut self) -> io::Result<()> {
+        self.close().await?;
+
+        let Some(quarantine) = self.quarantine.take() else {
+            return Ok(());
+        };
+
+        let stamp = OffsetDateTime::now_utc().unix_timestamp();
+        let mut name = quarantine.file_name().unwrap_or_default().to_os_string();
+        name.push(format!(".{stamp}.partial"));
+        let destination = quarantine.with_file_name(name);
+
+        if let Some(parent) = destination.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+
+        // Quarantine may live on another filesystem, so copy rather than rename
+        fs::copy(&self.temp, &destination).await?;
+        eprintln!("quarantined partial output at {}", destination.display());
+        Ok(())
+    }
+}
+
+impl Drop for PendingFile {
+    fn drop(&mut self) {
+        if !self.temp.as_os_str().is_empty() {
+            let _ = std::fs::remove_file(&self.temp);
+        }
+    }
+}
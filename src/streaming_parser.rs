@@ -5,6 +5,7 @@ pub struct StreamingParser {
     buffer: String,
     tag_depth: usize,
     top_level_tag_name: Option<String>,
+    closed: bool,
 }
 
 impl StreamingParser {
@@ -13,9 +14,15 @@ impl StreamingParser {
             buffer: String::new(),
             top_level_tag_name: None,
             tag_depth: 0,
+            closed: false,
         }
     }
 
+    /// Whether the closing `</_out>` tag has been seen, i.e. the file is complete.
+    pub fn is_closed(&self) -> bool {
+        self.closed
+    }
+
     pub fn feed(&mut self, chunk: &str) -> String {
         self.buffer.push_str(chunk);
 
@@ -68,6 +75,7 @@ impl StreamingParser {
             } else if self.tag_depth > 0 && self.top_level_tag_name.as_deref() == Some(tag_name) {
                 self.tag_depth -= 1;
                 if self.tag_depth == 0 {
+                    self.closed |= tag_name == OUT_TAG;
                     self.top_level_tag_name = None;
                 }
             }
@@ -0,0 +1,271 @@
+//! Separate versions of a page per query string or form submission, for search results, login
+//! results and similar views.
+//!
+//! Query strings are ignored unless `QUERY_PARAMS` names some parameters. A request carrying any
+//! of them gets a page of its own, generated with those parameters in the prompt. Forms posted
+//! to a page always get a response generated for their fields and the selected parameters of the
+//! URL they were posted to, but it is only kept when `CACHE_FORM_RESPONSES` is set. Values are
+//! trimmed with runs of whitespace collapsed and parameters sorted, so `?q=rust&utm_source=x` and
+//! `?q=%20rust` share one file.
+//!
+//! Variants are stored in the domain's hidden `.query/` and `.form/` directories, in a directory
+//! per page with files named by a hash of the normalized parameters. Their paths are therefore
+//! as long as the page's plus a constant, and the number of variants per page is capped. Being
+//! hidden, they never appear as context for other pages or in the index, and can't be requested
+//! directly, only through the URL of their page.
+use axum::http::StatusCode;
+use serde::Serialize;
+use std::borrow::Cow;
+use std::path::{Path, PathBuf};
+use tokio::fs;
+
+use crate::prompt;
+
+#[derive(Debug, Clone)]
+pub struct Variants {
+    names: Vec<String>,
+    max_query_length: usize,
+    max_form_size: usize,
+    max_variants: usize,
+}
+
+/// Where the parameters of a variant came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Source {
+    Query,
+    Form,
+}
+
+/// One parameter or form field, as given to the prompt templates.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+pub struct Param {
+    pub name: String,
+    pub value: String,
+}
+
+/// The parameters of a request, normalized.
+#[derive(Debug, Clone)]
+pub struct Variant {
+    pub source: Source,
+    pub params: Vec<Param>,
+    /// e.g. `page=2&q=rust+lang`
+    pub query: String,
+    /// Of a form response, the variant selected by the query string of the URL it was posted to.
+    pub selected: Option<Box<Variant>>,
+}
+
+impl Variants {
+    /// Query parameters `names` select a variant, with at most `max_query_length` bytes of them
+    /// after normalizing. Forms are limited to `max_form_size` bytes, and every page to
+    /// `max_variants` of each.
+    pub fn new(
+        names: Vec<String>,
+        max_query_length: usize,
+        max_form_size: usize,
+        max_variants: usize,
+    ) -> Self {
+        Self {
+            names,
+            max_query_length,
+            max_form_size,
+            max_variants,
+        }
+    }
+
+    /// The variant `query` asks for, `None` for the page itself.
+    pub fn select(&self, query: Option<&str>) -> Result<Option<Variant>, StatusCode> {
+        let Some(query) = query.filter(|_| !self.names.is_empty()) else {
+            return Ok(None);
+        };
+
+        let selected = form_urlencoded::parse(query.as_bytes())
+            .filter(|(name, _)| self.names.iter().any(|selected| selected == name));
+        let variant = Variant::new(Source::Query, selected);
+
+        if variant.params.is_empty() {
+            return Ok(None);
+        }
+        if variant.query.len() > self.max_query_length {
+            return Err(StatusCode::URI_TOO_LONG);
+        }
+
+        Ok(Some(variant))
+    }
+
+    /// The response to a form submitted with `fields` to a URL with `query`.
+    pub fn form(
+        &self,
+        query: Option<&str>,
+        fields: &[(String, String)],
+    ) -> Result<Variant, StatusCode> {
+        let fields = fields
+            .iter()
+            .map(|(name, value)| (Cow::from(name), Cow::from(value)));
+        let mut variant = Variant::new(Source::Form, fields);
+
+        if variant.query.len() > self.max_form_size {
+            return Err(StatusCode::PAYLOAD_TOO_LARGE);
+        }
+
+        variant.selected = self.select(query)?.map(Box::new);
+        Ok(variant)
+    }
+
+    /// Whether the directory of the variant stored at `path` has no room for another one.
+    pub async fn full(&self, root: &Path, path: &Path) -> bool {
+        let Some(dir) = path.parent() else {
+            return false;
+        };
+        let Ok(mut entries) = fs::read_dir(root.join(dir)).await else {
+            return false;
+        };
+
+        let mut count = 0;
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            // Temporary files of generations in progress are hidden
+            if !entry.file_name().to_string_lossy().starts_with('.') {
+                count += 1;
+            }
+        }
+
+        count >= self.max_variants
+    }
+}
+
+impl Variant {
+    fn new<'a>(source: Source, params: impl Iterator<Item = (Cow<'a, str>, Cow<'a, str>)>) -> Self {
+        let mut params: Vec<Param> = params
+            .map(|(name, value)| Param {
+                name: name.into_owned(),
+                value: value.split_whitespace().collect::<Vec<_>>().join(" "),
+            })
+            .filter(|param| !param.value.is_empty())
+            .collect();
+
+        params.sort();
+        params.dedup();
+
+        let query = form_urlencoded::Serializer::new(String::new())
+            .extend_pairs(params.iter().map(|param| (&param.name, &param.value)))
+            .finish();
+
+        Self {
+            source,
+            params,
+            query,
+            selected: None,
+        }
+    }
+
+    /// The query parameters this variant was requested with, itself unless it is a form
+    /// response.
+    pub fn selected(&self) -> Option<&Variant> {
+        match self.source {
+            Source::Query => Some(self),
+            Source::Form => self.selected.as_deref(),
+        }
+    }
+
+    /// Where this variant of the page stored at `page` is stored, e.g.
+    /// `google.com/.query/search.html/8c3f2a91d07be465.html` for `google.com/search.html`.
+    pub fn path(&self, page: &Path) -> PathBuf {
+        // Both are URL-encoded, so `?` separates them unambiguously
+        let key = match &self.selected {
+            Some(selected) => Cow::from(format!("{}?{}", self.query, selected.query)),
+            None => Cow::from(&self.query),
+        };
+
+        let hash = prompt::fnv1a(key.bytes());
+        let mut name = format!("{hash:016x}");
+        if let Some(extension) = page.extension() {
+            name = format!("{name}.{}", extension.to_string_lossy());
+        }
+
+        let dir = match self.source {
+            Source::Query => ".query",
+            Source::Form => ".form",
+        };
+
+        let mut components = page.iter();
+        let domain = components.next().unwrap_or_default();
+
+        Path::new(domain)
+            .join(dir)
+            .join(components.as_path())
+            .join(name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn variants() -> Variants {
+        Variants::new(vec!["q".to_string()], 256, 16 * 1024, 1000)
+    }
+
+    fn fields(fields: &[(&str, &str)]) -> Vec<(String, String)> {
+        fields
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn queries_are_normalized() {
+        let variants = variants();
+        let page = Path::new("google.com/search.html");
+
+        let a = variants.select(Some("q=%20rust&utm_source=x")).unwrap();
+        let b = variants.select(Some("q=rust")).unwrap();
+        assert_eq!(a.unwrap().path(page), b.unwrap().path(page));
+
+        assert!(variants.select(Some("utm_source=x")).unwrap().is_none());
+        assert!(variants.select(None).unwrap().is_none());
+    }
+
+    #[test]
+    fn forms_keep_the_query_they_were_posted_with() {
+        let variants = variants();
+        let page = Path::new("example.com/login.html");
+        let login = fields(&[("user", "ada")]);
+
+        let plain = variants.form(None, &login).unwrap();
+        let rust = variants.form(Some("q=rust&utm_source=x"), &login).unwrap();
+        let go = variants.form(Some("q=go"), &login).unwrap();
+
+        assert!(plain.selected().is_none());
+        assert_eq!(rust.selected().unwrap().query, "q=rust");
+        assert_eq!(rust.params, plain.params);
+
+        // Only selected parameters matter
+        let ignored = variants.form(Some("utm_source=x"), &login).unwrap();
+        assert_eq!(ignored.path(page), plain.path(page));
+
+        assert_ne!(rust.path(page), plain.path(page));
+        assert_ne!(rust.path(page), go.path(page));
+        assert!(rust.path(page).starts_with("example.com/.form/login.html"));
+    }
+
+    #[test]
+    fn limits_apply() {
+        let variants = Variants::new(vec!["q".to_string()], 8, 16, 1000);
+
+        assert_eq!(
+            variants.select(Some("q=far+too+long")).unwrap_err(),
+            StatusCode::URI_TOO_LONG
+        );
+        assert_eq!(
+            variants
+                .form(None, &fields(&[("message", "far too long")]))
+                .unwrap_err(),
+            StatusCode::PAYLOAD_TOO_LARGE
+        );
+        assert_eq!(
+            variants
+                .form(Some("q=far+too+long"), &fields(&[("a", "b")]))
+                .unwrap_err(),
+            StatusCode::URI_TOO_LONG
+        );
+    }
+}
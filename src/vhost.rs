@@ -0,0 +1,126 @@
+//! Virtual hosts, so every generated site gets an origin of its own.
+//!
+//! With `VHOST_SUFFIX=web2050.local`, a request for `example.com.web2050.local/about.html` is
+//! handled as `/example.com/about.html` would be, which makes root-relative links work and
+//! gives each site its own cookies and `localStorage`. Point a wildcard DNS record, or
+//! `/etc/hosts` entries, at the server. Any other host, like the suffix itself, still serves
+//! the index and the `/<domain>/...` paths.
+use axum::body::Body;
+use axum::extract::State;
+use axum::http::header::HOST;
+use axum::http::uri::PathAndQuery;
+use axum::http::{Request, Response, StatusCode, Uri};
+use axum::middleware::Next;
+use axum::response::IntoResponse;
+use std::sync::Arc;
+
+/// Files of the data directory every site shares, since the prompt links them from any domain.
+const SHARED: [&str; 2] = ["/tailwindcss.js", "/favicon.ico"];
+
+#[derive(Debug, Clone)]
+pub struct VirtualHosts {
+    /// e.g. `.web2050.local`
+    suffix: String,
+}
+
+impl VirtualHosts {
+    /// `suffix` without the leading dot, e.g. `web2050.local`.
+    pub fn new(suffix: &str) -> Self {
+        Self {
+            suffix: format!(".{}", suffix.trim_start_matches('.').to_lowercase()),
+        }
+    }
+
+    /// The domain `host` serves, e.g. `example.com` for `example.com.web2050.local:8080`.
+    pub fn domain(&self, host: &str) -> Option<String> {
+        let host = host.to_lowercase();
+        let host = match host.rsplit_once(':') {
+            Some((host, port)) if port.bytes().all(|b| b.is_ascii_digit()) => host,
+            _ => &host,
+        };
+
+        let domain = host.trim_end_matches('.').strip_suffix(&self.suffix)?;
+
+        // Never a path of the server's own, like `_admin`
+        let valid = domain
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'.');
+        (valid && domain.starts_with(|c: char| c.is_ascii_alphanumeric())).then(|| domain.into())
+    }
+
+    /// e.g. `.web2050.local`
+    pub fn suffix(&self) -> &str {
+        &self.suffix
+    }
+
+    /// The URL of `path` on the host of `domain`, e.g. `//example.com.web2050.local/about.html`.
+    /// Protocol-relative, since the server may sit behind TLS.
+    pub fn url(&self, domain: &str, path: &str) -> String {
+        format!("//{domain}{}{path}", self.suffix)
+    }
+
+    /// The path `uri` has on the main host, e.g. `/example.com/about.html` for
+    /// `http://example.com.web2050.local/about.html`. Other hosts keep their path.
+    pub fn path(&self, uri: &Uri) -> String {
+        match uri.host().and_then(|host| self.domain(host)) {
+            Some(domain) => format!("/{domain}{}", uri.path()),
+            None => uri.path().to_string(),
+        }
+    }
+}
+
+/// The link from the main host to the page at `path`, e.g. `example.com/about.html`: on the host
+/// of its domain with virtual hosts, and below the domain without.
+pub fn link(vhosts: Option<&VirtualHosts>, path: &str) -> String {
+    match (vhosts, path.split_once('/')) {
+        (Some(vhosts), Some((domain, rest))) => vhosts.url(domain, &format!("/{rest}")),
+        (Some(vhosts), None) => vhosts.url(path, "/"),
+        (None, _) => format!("/{path}"),
+    }
+}
+
+/// Whether `path` is served from the data directory on every host.
+pub fn shared(path: &str) -> bool {
+    SHARED.contains(&path)
+}
+
+/// Moves requests to a virtual host below its domain. Runs before routing, so a site gets every
+/// path, `/_meta` and `/` included.
+pub async fn route(
+    State(hosts): State<Arc<VirtualHosts>>,
+    mut req: Request<Body>,
+    next: Next,
+) -> Response<Body> {
+    let host = req
+        .uri()
+        .host()
+        .map(str::to_string)
+        .or_else(|| req.headers().get(HOST)?.to_str().ok().map(str::to_string));
+
+    let Some(domain) = host.and_then(|host| hosts.domain(&host)) else {
+        return next.run(req).await;
+    };
+
+    let path = req.uri().path();
+    if shared(path) {
+        return next.run(req).await;
+    }
+
+    let path_and_query = match req.uri().query() {
+        Some(query) => format!("/{domain}{path}?{query}"),
+        None => format!("/{domain}{path}"),
+    };
+
+    let mut parts = req.uri().clone().into_parts();
+    parts.path_and_query = match PathAndQuery::try_from(path_and_query) {
+        Ok(path_and_query) => Some(path_and_query),
+        Err(_) => return StatusCode::BAD_REQUEST.into_response(),
+    };
+
+    match Uri::from_parts(parts) {
+        Ok(uri) => *req.uri_mut() = uri,
+        Err(_) => return StatusCode::BAD_REQUEST.into_response(),
+    }
+
+    next.run(req).await
+}